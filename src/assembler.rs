@@ -0,0 +1,1008 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::diagnostics::{Diagnostic, Span};
+use crate::instruction::{self, Instruction};
+use crate::loader::ObjectFile;
+use crate::machine::MEMORY_SIZE;
+use crate::registers::RegisterName;
+use crate::symbols::SymbolTable;
+use crate::traps::Traps;
+
+/// The output of a successful assembly: the object image and its symbol table.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    pub object: ObjectFile,
+    pub symbols: SymbolTable,
+}
+
+impl Program {
+    /// Writes the `.obj` and `.sym` files for `source_path` alongside it, as the
+    /// reference assembler does.
+    pub fn write_files<P: AsRef<Path>>(&self, source_path: P) -> io::Result<()> {
+        let source_path = source_path.as_ref();
+        fs::write(source_path.with_extension("obj"), self.object.to_bytes())?;
+        fs::write(source_path.with_extension("sym"), self.symbols.to_string())
+    }
+}
+
+/// Every problem found in a source file, in source order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AssembleError {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl AssembleError {
+    /// Renders each diagnostic with an excerpt of `source`, which is named `file`.
+    pub fn render(&self, file: &str, source: &str) -> String {
+        self.diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.render(file, source))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Operand {
+    Register(u16),
+    Number(i32),
+    Label(String),
+    String(String),
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    operand: Operand,
+    span: Span,
+}
+
+/// One source line after lexing: an optional label and an optional operation.
+struct Statement {
+    line: usize,
+    label: Option<(String, Span)>,
+    operation: Option<(String, Span)>,
+    operands: Vec<Token>,
+}
+
+impl Statement {
+    fn operation(&self) -> Option<&str> {
+        self.operation.as_ref().map(|(name, _)| name.as_str())
+    }
+
+    /// Where to point errors about the statement as a whole.
+    fn span(&self) -> Span {
+        match (&self.operation, &self.label) {
+            (Some((_, span)), _) | (None, Some((_, span))) => *span,
+            (None, None) => Span::new(self.line, 1, 0),
+        }
+    }
+}
+
+/// Assembles LC-3 source in two passes: the first assigns an address to every
+/// label, the second encodes each statement. Errors are collected from both
+/// passes rather than stopping at the first one.
+pub fn assemble(source: &str) -> Result<Program, AssembleError> {
+    let mut diagnostics = Vec::new();
+    let statements = parse(source, &mut diagnostics);
+    let (origin, symbols) = assign_addresses(&statements, &mut diagnostics);
+    let mut words = Vec::new();
+    let mut address = origin as u32;
+    for statement in &statements {
+        let operation = match statement.operation() {
+            Some(".ORIG") | None => continue,
+            Some(".END") => break,
+            Some(operation) => operation,
+        };
+        //the first pass has already reported statements it could not size
+        let size = match size(statement) {
+            Ok(size) => size,
+            Err(_) => continue,
+        };
+        let encoded = match encode(statement, operation, address as u16, &symbols) {
+            Ok(encoded) => encoded,
+            Err(diagnostic) => {
+                diagnostics.push(diagnostic);
+                //keep later addresses in step with the first pass
+                vec![0; size as usize]
+            }
+        };
+        address += encoded.len() as u32;
+        words.extend(encoded);
+    }
+    if !diagnostics.is_empty() {
+        diagnostics.sort_by_key(|diagnostic| (diagnostic.span.line, diagnostic.span.column));
+        return Err(AssembleError { diagnostics });
+    }
+    Ok(Program {
+        object: ObjectFile { origin, words },
+        symbols,
+    })
+}
+
+fn parse(source: &str, diagnostics: &mut Vec<Diagnostic>) -> Vec<Statement> {
+    let mut statements = Vec::new();
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        match parse_line(line, text) {
+            Ok(Some(statement)) => {
+                let ended = statement.operation() == Some(".END");
+                statements.push(statement);
+                if ended {
+                    break;
+                }
+            }
+            Ok(None) => (),
+            Err(diagnostic) => diagnostics.push(diagnostic),
+        }
+    }
+    statements
+}
+
+fn parse_line(line: usize, text: &str) -> Result<Option<Statement>, Diagnostic> {
+    let mut tokens = lex(line, text)?.into_iter();
+    let mut statement = Statement {
+        line,
+        label: None,
+        operation: None,
+        operands: Vec::new(),
+    };
+    let mut next = tokens.next();
+    if let Some(Token {
+        operand: Operand::Label(word),
+        span,
+    }) = &next
+    {
+        if operation_name(word).is_none() {
+            let label = word.trim_end_matches(':');
+            if !is_valid_label(label) {
+                return Err(Diagnostic::new(*span, format!("invalid label {}", label)));
+            }
+            statement.label = Some((label.to_string(), *span));
+            next = tokens.next();
+        }
+    }
+    match next {
+        Some(Token {
+            operand: Operand::Label(word),
+            span,
+        }) => match operation_name(&word) {
+            Some(operation) => statement.operation = Some((operation, span)),
+            None => {
+                return Err(Diagnostic::new(
+                    span,
+                    format!("unknown instruction {}", word),
+                ))
+            }
+        },
+        //a "label" followed by operands is more likely a misspelled instruction
+        Some(token) => {
+            return Err(match statement.label {
+                Some((label, span)) => {
+                    Diagnostic::new(span, format!("unknown instruction {}", label))
+                }
+                None => Diagnostic::new(token.span, "expected a label or instruction"),
+            })
+        }
+        None => (),
+    }
+    statement.operands = tokens.collect();
+    if statement.label.is_none() && statement.operation.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(statement))
+}
+
+/// Splits a line into tokens, treating commas as whitespace and dropping comments.
+fn lex(line: usize, text: &str) -> Result<Vec<Token>, Diagnostic> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+    while index < chars.len() {
+        let c = chars[index];
+        let start = index;
+        if c.is_whitespace() || c == ',' {
+            index += 1;
+            continue;
+        } else if c == ';' {
+            break;
+        }
+        let operand = if c == '"' {
+            let unterminated = Diagnostic::new(
+                Span::new(line, start + 1, chars.len() - start),
+                "unterminated string",
+            );
+            index += 1;
+            let mut string = String::new();
+            loop {
+                match chars.get(index) {
+                    Some('"') => break,
+                    Some('\\') => {
+                        index += 1;
+                        match chars.get(index) {
+                            Some('n') => string.push('\n'),
+                            Some('t') => string.push('\t'),
+                            Some('r') => string.push('\r'),
+                            Some('0') => string.push('\0'),
+                            Some('e') => string.push('\x1B'),
+                            Some(&escaped @ ('"' | '\\')) => string.push(escaped),
+                            Some(other) => {
+                                return Err(Diagnostic::new(
+                                    Span::new(line, index, 2),
+                                    format!("unknown escape sequence \\{}", other),
+                                ))
+                            }
+                            None => return Err(unterminated),
+                        }
+                    }
+                    Some(&c) => string.push(c),
+                    None => return Err(unterminated),
+                }
+                index += 1;
+            }
+            index += 1;
+            Operand::String(string)
+        } else {
+            while index < chars.len() {
+                let c = chars[index];
+                if c.is_whitespace() || c == ',' || c == ';' || c == '"' {
+                    break;
+                }
+                index += 1;
+            }
+            let word: String = chars[start..index].iter().collect();
+            classify(&word)
+        };
+        tokens.push(Token {
+            operand,
+            span: Span::new(line, start + 1, index - start),
+        });
+    }
+    Ok(tokens)
+}
+
+fn classify(word: &str) -> Operand {
+    if let Some(register) = parse_register(word) {
+        Operand::Register(register)
+    } else if let Some(number) = parse_number(word) {
+        Operand::Number(number)
+    } else {
+        Operand::Label(word.to_string())
+    }
+}
+
+fn parse_register(word: &str) -> Option<u16> {
+    let bytes = word.as_bytes();
+    if bytes.len() == 2
+        && (bytes[0] == b'R' || bytes[0] == b'r')
+        && (b'0'..=b'7').contains(&bytes[1])
+    {
+        Some((bytes[1] - b'0') as u16)
+    } else {
+        None
+    }
+}
+
+/// Parses `#10`, `10`, `-10`, `x3000`, `0x3000`, `b1010` and `0b1010`.
+fn parse_number(word: &str) -> Option<i32> {
+    let lower = word.to_ascii_lowercase();
+    let (radix, digits) = if let Some(digits) = lower.strip_prefix('#') {
+        (10, digits)
+    } else if let Some(digits) = lower.strip_prefix("0x").or_else(|| lower.strip_prefix('x')) {
+        (16, digits)
+    } else if let Some(digits) = lower.strip_prefix("0b").or_else(|| lower.strip_prefix('b')) {
+        (2, digits)
+    } else {
+        (10, lower.as_str())
+    };
+    let (negative, magnitude) = match digits.strip_prefix('-') {
+        Some(magnitude) => (true, magnitude),
+        None => (false, digits),
+    };
+    if magnitude.is_empty() || !magnitude.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    let value = i32::from_str_radix(magnitude, radix).ok()?;
+    Some(if negative { -value } else { value })
+}
+
+fn is_valid_label(label: &str) -> bool {
+    let mut chars = label.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+/// The canonical (upper case) name of an instruction or directive.
+fn operation_name(word: &str) -> Option<String> {
+    let upper = word.to_ascii_uppercase();
+    let known = match upper.as_str() {
+        "ADD" | "AND" | "NOT" | "JMP" | "JSR" | "JSRR" | "LD" | "LDI" | "LDR" | "LEA" | "RET"
+        | "RTI" | "ST" | "STI" | "STR" | "TRAP" | "GETC" | "OUT" | "PUTS" | "IN" | "PUTSP"
+        | "HALT" | ".ORIG" | ".FILL" | ".BLKW" | ".STRINGZ" | ".END" => true,
+        other => branch_condition(other).is_some(),
+    };
+    if known {
+        Some(upper)
+    } else {
+        None
+    }
+}
+
+/// The nzp bits of a BR mnemonic; plain BR branches unconditionally.
+fn branch_condition(upper: &str) -> Option<u16> {
+    let flags = upper.strip_prefix("BR")?;
+    if flags.is_empty() {
+        return Some(0b111);
+    }
+    let mut nzp = 0;
+    let mut rest = flags;
+    for (flag, bit) in [('N', 0b100), ('Z', 0b010), ('P', 0b001)] {
+        if let Some(remaining) = rest.strip_prefix(flag) {
+            nzp |= bit;
+            rest = remaining;
+        }
+    }
+    if rest.is_empty() {
+        Some(nzp)
+    } else {
+        None
+    }
+}
+
+/// First pass: finds the origin and the address of every label. Without a
+/// usable `.ORIG` addresses are counted from zero so later errors still surface.
+fn assign_addresses(
+    statements: &[Statement],
+    diagnostics: &mut Vec<Diagnostic>,
+) -> (u16, SymbolTable) {
+    let mut symbols = SymbolTable::new();
+    let mut origin = None;
+    let mut address: u32 = 0;
+    let mut reported_missing_origin = false;
+    for statement in statements {
+        if statement.operation() == Some(".ORIG") {
+            match orig_address(statement, origin.is_some()) {
+                Ok(value) => {
+                    origin = Some(value);
+                    address = value as u32;
+                }
+                Err(diagnostic) => diagnostics.push(diagnostic),
+            }
+            continue;
+        }
+        if origin.is_none() && !reported_missing_origin {
+            reported_missing_origin = true;
+            diagnostics.push(Diagnostic::new(
+                statement.span(),
+                "expected .ORIG before the first statement",
+            ));
+        }
+        if let Some((label, span)) = &statement.label {
+            if !symbols.insert(label, address as u16) {
+                diagnostics.push(Diagnostic::new(*span, format!("duplicate label {}", label)));
+            }
+        }
+        if statement.operation() == Some(".END") {
+            break;
+        }
+        match size(statement) {
+            Ok(size) => address += size,
+            Err(diagnostic) => diagnostics.push(diagnostic),
+        }
+        if address as usize > MEMORY_SIZE {
+            diagnostics.push(Diagnostic::new(
+                statement.span(),
+                "program runs past the end of memory",
+            ));
+            break;
+        }
+    }
+    if statements.is_empty() {
+        diagnostics.push(Diagnostic::new(Span::new(1, 1, 0), "missing .ORIG"));
+    }
+    (origin.unwrap_or(0), symbols)
+}
+
+fn orig_address(statement: &Statement, seen: bool) -> Result<u16, Diagnostic> {
+    if seen {
+        return Err(Diagnostic::new(
+            statement.span(),
+            "only one .ORIG is allowed",
+        ));
+    }
+    if let Some((_, span)) = &statement.label {
+        return Err(Diagnostic::new(*span, ".ORIG cannot be labeled"));
+    }
+    match statement.operands.as_slice() {
+        [Token {
+            operand: Operand::Number(value),
+            ..
+        }] if (0..=0xFFFF).contains(value) => Ok(*value as u16),
+        [Token {
+            operand: Operand::Number(value),
+            span,
+        }] => Err(Diagnostic::new(
+            *span,
+            format!("origin {} is not an address", value),
+        )),
+        _ => Err(Diagnostic::new(
+            statement.span(),
+            ".ORIG expects an address",
+        )),
+    }
+}
+
+/// The number of words a statement occupies.
+fn size(statement: &Statement) -> Result<u32, Diagnostic> {
+    let operands: Vec<&Operand> = statement
+        .operands
+        .iter()
+        .map(|token| &token.operand)
+        .collect();
+    match statement.operation() {
+        None => Ok(0),
+        Some(".BLKW") => match operands.as_slice() {
+            [Operand::Number(count)] if *count > 0 => Ok(*count as u32),
+            _ => Err(Diagnostic::new(
+                statement.span(),
+                ".BLKW expects a positive count",
+            )),
+        },
+        Some(".STRINGZ") => match operands.as_slice() {
+            [Operand::String(string)] => Ok(string.chars().count() as u32 + 1),
+            _ => Err(Diagnostic::new(
+                statement.span(),
+                ".STRINGZ expects a string",
+            )),
+        },
+        Some(_) => Ok(1),
+    }
+}
+
+/// Second pass: encodes one statement located at `address`.
+fn encode(
+    statement: &Statement,
+    operation: &str,
+    address: u16,
+    symbols: &SymbolTable,
+) -> Result<Vec<u16>, Diagnostic> {
+    let encoder = Encoder {
+        statement,
+        operation,
+        address,
+        symbols,
+    };
+    encoder.encode()
+}
+
+struct Encoder<'a> {
+    statement: &'a Statement,
+    operation: &'a str,
+    address: u16,
+    symbols: &'a SymbolTable,
+}
+
+impl<'a> Encoder<'a> {
+    fn encode(&self) -> Result<Vec<u16>, Diagnostic> {
+        let word = match self.operation {
+            ".FILL" => {
+                self.expect_operands(1)?;
+                let token = &self.statement.operands[0];
+                match &token.operand {
+                    Operand::Number(value) if (-0x8000..=0xFFFF).contains(value) => *value as u16,
+                    Operand::Number(value) => {
+                        return Err(Diagnostic::new(
+                            token.span,
+                            format!("{} does not fit in a word", value),
+                        ))
+                    }
+                    Operand::Label(label) => self.resolve(label, token.span)?,
+                    _ => {
+                        return Err(Diagnostic::new(
+                            token.span,
+                            ".FILL expects a number or label",
+                        ))
+                    }
+                }
+            }
+            ".BLKW" => return Ok(vec![0; size(self.statement)? as usize]),
+            ".STRINGZ" => {
+                let mut words: Vec<u16> = match self.statement.operands.as_slice() {
+                    [Token {
+                        operand: Operand::String(string),
+                        ..
+                    }] => string.chars().map(|c| c as u16).collect(),
+                    _ => {
+                        return Err(Diagnostic::new(
+                            self.statement.span(),
+                            ".STRINGZ expects a string",
+                        ))
+                    }
+                };
+                words.push(0);
+                return Ok(words);
+            }
+            _ => self.instruction()?.encode(),
+        };
+        Ok(vec![word])
+    }
+
+    fn instruction(&self) -> Result<Instruction, Diagnostic> {
+        let instruction = match self.operation {
+            "ADD" => {
+                let (dr, sr1, operand) = self.arithmetic()?;
+                Instruction::Add { dr, sr1, operand }
+            }
+            "AND" => {
+                let (dr, sr1, operand) = self.arithmetic()?;
+                Instruction::And { dr, sr1, operand }
+            }
+            "NOT" => {
+                self.expect_operands(2)?;
+                Instruction::Not {
+                    dr: self.register(0)?,
+                    sr: self.register(1)?,
+                }
+            }
+            "JMP" => {
+                self.expect_operands(1)?;
+                Instruction::Jmp {
+                    base: self.register(0)?,
+                }
+            }
+            "RET" => {
+                self.expect_operands(0)?;
+                Instruction::Jmp {
+                    base: RegisterName::R7,
+                }
+            }
+            "JSR" => {
+                self.expect_operands(1)?;
+                Instruction::Jsr {
+                    offset11: self.pc_offset(0, 11)?,
+                }
+            }
+            "JSRR" => {
+                self.expect_operands(1)?;
+                Instruction::Jsrr {
+                    base: self.register(0)?,
+                }
+            }
+            "LD" => {
+                let (dr, offset9) = self.pc_relative()?;
+                Instruction::Ld { dr, offset9 }
+            }
+            "LDI" => {
+                let (dr, offset9) = self.pc_relative()?;
+                Instruction::Ldi { dr, offset9 }
+            }
+            "LEA" => {
+                let (dr, offset9) = self.pc_relative()?;
+                Instruction::Lea { dr, offset9 }
+            }
+            "ST" => {
+                let (sr, offset9) = self.pc_relative()?;
+                Instruction::St { sr, offset9 }
+            }
+            "STI" => {
+                let (sr, offset9) = self.pc_relative()?;
+                Instruction::Sti { sr, offset9 }
+            }
+            "LDR" => {
+                let (dr, base, offset6) = self.base_offset()?;
+                Instruction::Ldr { dr, base, offset6 }
+            }
+            "STR" => {
+                let (sr, base, offset6) = self.base_offset()?;
+                Instruction::Str { sr, base, offset6 }
+            }
+            "RTI" => {
+                self.expect_operands(0)?;
+                Instruction::Rti
+            }
+            "TRAP" => {
+                self.expect_operands(1)?;
+                Instruction::Trap {
+                    trapvect8: self.trap_vector(0)?,
+                }
+            }
+            "GETC" => self.trap_alias(Traps::Getc)?,
+            "OUT" => self.trap_alias(Traps::Out)?,
+            "PUTS" => self.trap_alias(Traps::Puts)?,
+            "IN" => self.trap_alias(Traps::In)?,
+            "PUTSP" => self.trap_alias(Traps::Putsp)?,
+            "HALT" => self.trap_alias(Traps::Halt)?,
+            operation => match branch_condition(operation) {
+                Some(nzp) => {
+                    self.expect_operands(1)?;
+                    Instruction::Br {
+                        nzp: nzp as u8,
+                        offset9: self.pc_offset(0, 9)?,
+                    }
+                }
+                None => unreachable!("operation names are validated while parsing"),
+            },
+        };
+        Ok(instruction)
+    }
+
+    fn expect_operands(&self, count: usize) -> Result<(), Diagnostic> {
+        let found = self.statement.operands.len();
+        if found != count {
+            let span = match self.statement.operands.get(count) {
+                Some(extra) => extra.span,
+                None => self.statement.span(),
+            };
+            return Err(Diagnostic::new(
+                span,
+                format!(
+                    "{} expects {} operand{}, found {}",
+                    self.operation,
+                    count,
+                    if count == 1 { "" } else { "s" },
+                    found
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn register(&self, index: usize) -> Result<RegisterName, Diagnostic> {
+        let token = &self.statement.operands[index];
+        match &token.operand {
+            Operand::Register(register) => Ok(RegisterName::from_field(*register)),
+            _ => Err(Diagnostic::new(
+                token.span,
+                format!("operand {} must be a register", index + 1),
+            )),
+        }
+    }
+
+    fn resolve(&self, label: &str, span: Span) -> Result<u16, Diagnostic> {
+        match self.symbols.get(label) {
+            Some(address) => Ok(address),
+            None => Err(Diagnostic::new(span, format!("undefined label {}", label))),
+        }
+    }
+
+    /// A signed `field` of `bits` bits, checked to fit.
+    fn signed(&self, token: &Token, value: i32, bits: u32, field: &str) -> Result<i16, Diagnostic> {
+        let limit = 1 << (bits - 1);
+        if value < -limit || value >= limit {
+            let kind = if field == "imm5" {
+                "immediate"
+            } else {
+                "offset"
+            };
+            return Err(Diagnostic::new(
+                token.span,
+                format!("{} {} out of range for {}", kind, value, field),
+            ));
+        }
+        Ok(value as i16)
+    }
+
+    fn trap_vector(&self, index: usize) -> Result<u8, Diagnostic> {
+        let token = &self.statement.operands[index];
+        match &token.operand {
+            Operand::Number(value) if (0..=0xFF).contains(value) => Ok(*value as u8),
+            Operand::Number(value) => Err(Diagnostic::new(
+                token.span,
+                format!("vector {} out of range for trapvect8", value),
+            )),
+            _ => Err(Diagnostic::new(
+                token.span,
+                format!("operand {} must be a number", index + 1),
+            )),
+        }
+    }
+
+    /// A label (or literal offset) encoded relative to the incremented PC.
+    fn pc_offset(&self, index: usize, bits: u32) -> Result<i16, Diagnostic> {
+        let token = &self.statement.operands[index];
+        let offset = match &token.operand {
+            Operand::Label(label) => {
+                self.resolve(label, token.span)? as i32 - (self.address as i32 + 1)
+            }
+            Operand::Number(offset) => *offset,
+            _ => {
+                return Err(Diagnostic::new(
+                    token.span,
+                    format!("operand {} must be a label", index + 1),
+                ))
+            }
+        };
+        self.signed(token, offset, bits, &format!("PCoffset{}", bits))
+    }
+
+    fn arithmetic(&self) -> Result<(RegisterName, RegisterName, instruction::Operand), Diagnostic> {
+        self.expect_operands(3)?;
+        let token = &self.statement.operands[2];
+        let operand = match &token.operand {
+            Operand::Register(sr2) => {
+                instruction::Operand::Register(RegisterName::from_field(*sr2))
+            }
+            Operand::Number(value) => {
+                instruction::Operand::Immediate(self.signed(token, *value, 5, "imm5")?)
+            }
+            _ => {
+                return Err(Diagnostic::new(
+                    token.span,
+                    "operand 3 must be a register or number",
+                ))
+            }
+        };
+        Ok((self.register(0)?, self.register(1)?, operand))
+    }
+
+    fn pc_relative(&self) -> Result<(RegisterName, i16), Diagnostic> {
+        self.expect_operands(2)?;
+        Ok((self.register(0)?, self.pc_offset(1, 9)?))
+    }
+
+    fn base_offset(&self) -> Result<(RegisterName, RegisterName, i16), Diagnostic> {
+        self.expect_operands(3)?;
+        let token = &self.statement.operands[2];
+        let offset = match &token.operand {
+            Operand::Number(offset) => self.signed(token, *offset, 6, "offset6")?,
+            _ => return Err(Diagnostic::new(token.span, "operand 3 must be a number")),
+        };
+        Ok((self.register(0)?, self.register(1)?, offset))
+    }
+
+    fn trap_alias(&self, trap: Traps) -> Result<Instruction, Diagnostic> {
+        self.expect_operands(0)?;
+        Ok(Instruction::Trap {
+            trapvect8: trap as u8,
+        })
+    }
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, diagnostic) in self.diagnostics.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for AssembleError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(source: &str) -> Vec<u16> {
+        assemble(source).unwrap().object.words
+    }
+
+    fn error_message(source: &str) -> String {
+        assemble(source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn it_assembles_every_instruction() {
+        let source = "
+            .ORIG x3000
+            ADD R3, R1, R2
+            ADD R0, R0, #-1
+            AND R5, R5, #0
+            AND R2 R1 R3
+            NOT R1, R2
+            BR #-1
+            BRnzp #0
+            BRz #1
+            BRnp #2
+            LD R2, #3
+            LDI R3, #1
+            LDR R4, R1, #-3
+            LEA R5, #-16
+            ST R6, #2
+            STI R7, #1
+            STR R0, R6, #-32
+            JMP R2
+            RET
+            JSR #-1024
+            JSRR R3
+            RTI
+            TRAP x40
+            GETC
+            OUT
+            PUTS
+            IN
+            PUTSP
+            HALT
+            .END
+        ";
+        assert_eq!(
+            words(source),
+            vec![
+                0x1642, 0x103F, 0x5B60, 0x5443, 0x92BF, 0x0FFF, 0x0E00, 0x0401, 0x0A02, 0x2403,
+                0xA601, 0x687D, 0xEBF0, 0x3C02, 0xBE01, 0x71A0, 0xC080, 0xC1C0, 0x4C00, 0x40C0,
+                0x8000, 0xF040, 0xF020, 0xF021, 0xF022, 0xF023, 0xF024, 0xF025,
+            ]
+        );
+    }
+
+    #[test]
+    fn it_resolves_labels_in_both_directions() {
+        let program = assemble(
+            "      .orig x3000
+            LOOP  add r1, r1, #-1 ; count down
+                  brp LOOP
+                  jsr DONE
+                  ld r0, VALUE
+            DONE  halt
+            VALUE .fill DONE
+                  .end",
+        )
+        .unwrap();
+        assert_eq!(
+            program.object.words,
+            vec![0x127F, 0x03FE, 0x4801, 0x2001, 0xF025, 0x3004]
+        );
+        assert_eq!(program.symbols.get("LOOP"), Some(0x3000));
+        assert_eq!(program.symbols.get("DONE"), Some(0x3004));
+        assert_eq!(program.symbols.get("VALUE"), Some(0x3005));
+    }
+
+    #[test]
+    fn it_assembles_data_directives() {
+        let program = assemble(
+            ".ORIG x4000
+            A .FILL b1010
+            B .FILL #-1
+            C .BLKW 2
+            D .STRINGZ \"hi\\n\"
+            E .FILL 0x7
+            .END",
+        )
+        .unwrap();
+        assert_eq!(program.object.origin, 0x4000);
+        assert_eq!(
+            program.object.words,
+            vec![
+                10,
+                0xFFFF,
+                0,
+                0,
+                b'h' as u16,
+                b'i' as u16,
+                b'\n' as u16,
+                0,
+                7
+            ]
+        );
+        assert_eq!(program.symbols.get("E"), Some(0x4008));
+    }
+
+    #[test]
+    fn it_produces_programs_the_machine_can_run() {
+        use crate::console::BufferConsole;
+        use crate::machine::{Machine, StopReason};
+
+        let program = assemble(
+            ".ORIG x3000
+                LEA R0, GREETING
+                PUTS
+                HALT
+            GREETING .STRINGZ \"Hello\"
+            .END",
+        )
+        .unwrap();
+        let console = BufferConsole::default();
+        let mut machine = Machine::empty()
+            .with_console(Box::new(console.clone()))
+            .start();
+        machine.load_object(&program.object);
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert!(console.output_string().starts_with("Hello"));
+    }
+
+    #[test]
+    fn it_ignores_everything_after_end() {
+        assert_eq!(
+            words(".ORIG x3000\nHALT\n.END\nthis is not assembly"),
+            vec![0xF025]
+        );
+    }
+
+    #[test]
+    fn it_reports_undefined_labels() {
+        assert_eq!(
+            error_message(".ORIG x3000\nBR LOOP\n.END"),
+            "2:4: undefined label LOOP"
+        );
+    }
+
+    #[test]
+    fn it_reports_out_of_range_offsets() {
+        assert_eq!(
+            error_message(".ORIG x3000\nBR #300\n.END"),
+            "2:4: offset 300 out of range for PCoffset9"
+        );
+        assert_eq!(
+            error_message(".ORIG x3000\nADD R0, R0, #16\n.END"),
+            "2:13: immediate 16 out of range for imm5"
+        );
+    }
+
+    #[test]
+    fn it_reports_malformed_statements() {
+        assert_eq!(
+            error_message("HALT"),
+            "1:1: expected .ORIG before the first statement"
+        );
+        assert_eq!(
+            error_message(".ORIG x3000\nADD R0, R0\n.END"),
+            "2:1: ADD expects 3 operands, found 2"
+        );
+        assert_eq!(
+            error_message(".ORIG x3000\nX HALT\nX HALT\n.END"),
+            "3:1: duplicate label X"
+        );
+        assert_eq!(
+            error_message(".ORIG x3000\nMOV R0, R1\n.END"),
+            "2:1: unknown instruction MOV"
+        );
+        assert_eq!(
+            error_message(".ORIG x3000\nHALT R0\n.END"),
+            "2:6: HALT expects 0 operands, found 1"
+        );
+    }
+
+    #[test]
+    fn it_reports_malformed_data_directives_once() {
+        assert_eq!(
+            error_message(".ORIG x3000\n.STRINGZ 5\n.END"),
+            "2:1: .STRINGZ expects a string"
+        );
+        assert_eq!(
+            error_message(".ORIG x3000\n.STRINGZ\n.END"),
+            "2:1: .STRINGZ expects a string"
+        );
+        assert_eq!(
+            error_message(".ORIG x3000\n.BLKW #-2\n.END"),
+            "2:1: .BLKW expects a positive count"
+        );
+    }
+
+    #[test]
+    fn it_collects_every_error_in_source_order() {
+        let source = ".ORIG x3000
+LOOP ADD R0, R0, #99
+     BR DONE
+     .STRINGZ \"oops
+     LD R1, LOOP
+     .END";
+        let error = assemble(source).unwrap_err();
+        let messages: Vec<String> = error
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "2:18: immediate 99 out of range for imm5",
+                "3:9: undefined label DONE",
+                "4:15: unterminated string",
+            ]
+        );
+        let rendered = error.render("loop.asm", source);
+        assert!(rendered.contains(
+            "error: undefined label DONE\n --> loop.asm:3:9\n  |\n3 |      BR DONE\n  |         ^^^^\n"
+        ));
+    }
+}
@@ -0,0 +1,193 @@
+use lc3::util::parse_word;
+
+pub const USAGE: &str = "\
+usage: lc3 run [options] FILE.obj...
+       lc3 debug [options] FILE.obj...
+       lc3 gdb [--port PORT] [options] FILE.obj...
+       lc3 diff-trace OURS THEIRS
+
+Loads each object file and runs from the origin of the first. `debug` starts
+an interactive debugger there instead; symbols are read from FILE.sym when it
+exists. `gdb` waits for a GDB remote protocol client on localhost, port 1234
+by default. `diff-trace` compares two traces, text or binary, and reports the
+first instruction at which they diverge.
+
+options:
+    --pc ADDRESS    start at ADDRESS instead, e.g. x3000
+    --os            load the bundled operating system and use its trap routines
+    --snapshot FILE resume the machine saved in FILE; object files are optional
+    --trace FILE    write a line to FILE for each executed instruction
+    --trace-binary FILE
+                    write the trace in the compact binary format instead
+";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run(RunOptions),
+    Debug(RunOptions),
+    Gdb { options: RunOptions, port: u16 },
+    DiffTrace { ours: String, theirs: String },
+    Help,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct RunOptions {
+    pub files: Vec<String>,
+    pub pc: Option<u16>,
+    pub os: bool,
+    pub snapshot: Option<String>,
+    pub trace: Option<String>,
+    pub binary_trace: bool,
+}
+
+/// Parses the arguments after the program name. For compatibility, arguments
+/// that do not start with a subcommand are taken as `run`'s.
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    match args.first().map(String::as_str) {
+        None | Some("help") | Some("--help") | Some("-h") => Ok(Command::Help),
+        Some("run") => parse_run(&args[1..]).map(Command::Run),
+        Some("debug") => parse_run(&args[1..]).map(Command::Debug),
+        Some("gdb") => parse_gdb(&args[1..]),
+        Some("diff-trace") => match &args[1..] {
+            [ours, theirs] => Ok(Command::DiffTrace {
+                ours: ours.clone(),
+                theirs: theirs.clone(),
+            }),
+            _ => Err("diff-trace expects two trace files".to_string()),
+        },
+        Some(_) => parse_run(args).map(Command::Run),
+    }
+}
+
+pub const DEFAULT_GDB_PORT: u16 = 1234;
+
+fn parse_gdb(args: &[String]) -> Result<Command, String> {
+    let mut port = DEFAULT_GDB_PORT;
+    let mut rest = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--port" {
+            let value = args.next().ok_or("--port expects a port number")?;
+            port = value
+                .parse()
+                .map_err(|_| format!("invalid port {}", value))?;
+        } else {
+            rest.push(arg.clone());
+        }
+    }
+    let options = parse_run(&rest)?;
+    Ok(Command::Gdb { options, port })
+}
+
+fn parse_run(args: &[String]) -> Result<RunOptions, String> {
+    let mut options = RunOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--pc" => {
+                let value = args.next().ok_or("--pc expects an address")?;
+                let pc = parse_word(value).ok_or(format!("invalid address {}", value))?;
+                options.pc = Some(pc);
+            }
+            "--os" => options.os = true,
+            "--snapshot" => {
+                let path = args.next().ok_or("--snapshot expects a file")?;
+                options.snapshot = Some(path.to_string());
+            }
+            "--trace" | "--trace-binary" => {
+                let path = args.next().ok_or(format!("{} expects a file", arg))?;
+                options.trace = Some(path.to_string());
+                options.binary_trace = arg == "--trace-binary";
+            }
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+            file => options.files.push(file.to_string()),
+        }
+    }
+    if options.files.is_empty() && options.snapshot.is_none() {
+        return Err("no object files given".to_string());
+    }
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(text: &str) -> Vec<String> {
+        text.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn it_parses_run_options() {
+        assert_eq!(
+            parse(&args("run --os a.obj --pc x3100 b.obj")),
+            Ok(Command::Run(RunOptions {
+                files: vec!["a.obj".to_string(), "b.obj".to_string()],
+                pc: Some(0x3100),
+                os: true,
+                snapshot: None,
+                trace: None,
+                binary_trace: false,
+            }))
+        );
+        assert_eq!(
+            parse(&args("a.obj")),
+            Ok(Command::Run(RunOptions {
+                files: vec!["a.obj".to_string()],
+                ..RunOptions::default()
+            }))
+        );
+        assert_eq!(
+            parse(&args("debug --os a.obj")),
+            Ok(Command::Debug(RunOptions {
+                files: vec!["a.obj".to_string()],
+                os: true,
+                ..RunOptions::default()
+            }))
+        );
+        assert_eq!(
+            parse(&args("gdb a.obj --port 4000")),
+            Ok(Command::Gdb {
+                options: RunOptions {
+                    files: vec!["a.obj".to_string()],
+                    ..RunOptions::default()
+                },
+                port: 4000
+            })
+        );
+        assert_eq!(
+            parse(&args("run --snapshot paused.snap")),
+            Ok(Command::Run(RunOptions {
+                snapshot: Some("paused.snap".to_string()),
+                ..RunOptions::default()
+            }))
+        );
+        assert_eq!(
+            parse(&args("run --trace-binary run.trace a.obj")),
+            Ok(Command::Run(RunOptions {
+                files: vec!["a.obj".to_string()],
+                trace: Some("run.trace".to_string()),
+                binary_trace: true,
+                ..RunOptions::default()
+            }))
+        );
+        assert_eq!(
+            parse(&args("diff-trace ours.trace theirs.trace")),
+            Ok(Command::DiffTrace {
+                ours: "ours.trace".to_string(),
+                theirs: "theirs.trace".to_string()
+            })
+        );
+        assert_eq!(parse(&args("")), Ok(Command::Help));
+    }
+
+    #[test]
+    fn it_rejects_bad_run_options() {
+        assert!(parse(&args("run")).is_err());
+        assert!(parse(&args("run --pc a.obj")).is_err());
+        assert!(parse(&args("run --pc zz a.obj")).is_err());
+        assert!(parse(&args("run --fast a.obj")).is_err());
+        assert!(parse(&args("gdb --port 99999 a.obj")).is_err());
+        assert!(parse(&args("diff-trace ours.trace")).is_err());
+    }
+}
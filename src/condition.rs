@@ -0,0 +1,341 @@
+use std::fmt;
+
+use crate::machine::Machine;
+use crate::registers::RegisterName;
+use crate::symbols::SymbolTable;
+use crate::util::parse_word;
+
+/// A boolean expression over the machine state, e.g. `R0 == x41 && mem[x4000] > 3`.
+///
+/// Operands are R0-R7, PC, PSR, the condition codes N, Z and P (1 when set),
+/// `mem[...]`, numbers and labels. `mem[...]` sees the MCR and device
+/// registers as the debugger does, without the side effects of reading them.
+/// Comparisons are signed. A value on its own is true when it is nonzero.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Condition {
+    source: String,
+    expression: Expression,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expression {
+    Constant(u16),
+    Register(RegisterName),
+    //the PSR bit of a condition code
+    Flag(u16),
+    Memory(Box<Expression>),
+    Not(Box<Expression>),
+    Compare(Box<Expression>, Comparison, Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Condition {
+    /// Parses `source`, resolving labels with `symbols` if given.
+    pub fn parse(source: &str, symbols: Option<&SymbolTable>) -> Result<Condition, String> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+            depth: 0,
+            symbols,
+        };
+        let expression = parser.or()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("unexpected {} in condition", token));
+        }
+        Ok(Condition {
+            source: source.trim().to_string(),
+            expression,
+        })
+    }
+
+    /// Evaluates the condition without side effects on the machine.
+    pub fn evaluate(&self, machine: &Machine) -> bool {
+        self.expression.evaluate(machine) != 0
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl Expression {
+    fn evaluate(&self, machine: &Machine) -> u16 {
+        match self {
+            Expression::Constant(value) => *value,
+            Expression::Register(register) => machine.registers().get_by_name(*register),
+            Expression::Flag(bit) => (machine.registers().get_psr() & bit != 0) as u16,
+            Expression::Memory(address) => machine.peek(address.evaluate(machine)),
+            Expression::Not(operand) => (operand.evaluate(machine) == 0) as u16,
+            Expression::Compare(left, comparison, right) => {
+                let left = left.evaluate(machine) as i16;
+                let right = right.evaluate(machine) as i16;
+                let holds = match comparison {
+                    Comparison::Equal => left == right,
+                    Comparison::NotEqual => left != right,
+                    Comparison::Less => left < right,
+                    Comparison::LessOrEqual => left <= right,
+                    Comparison::Greater => left > right,
+                    Comparison::GreaterOrEqual => left >= right,
+                };
+                holds as u16
+            }
+            Expression::And(left, right) => {
+                (left.evaluate(machine) != 0 && right.evaluate(machine) != 0) as u16
+            }
+            Expression::Or(left, right) => {
+                (left.evaluate(machine) != 0 || right.evaluate(machine) != 0) as u16
+            }
+        }
+    }
+}
+
+const SYMBOLS: [&str; 13] = [
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "[", "]", "(", ")",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{}", word),
+            Token::Symbol(symbol) => write!(f, "{}", symbol),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = source.trim_start();
+    while !rest.is_empty() {
+        if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(*symbol)) {
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        } else {
+            //words are labels, registers and numbers, including #-1
+            let end = rest
+                .char_indices()
+                .find(|&(index, c)| {
+                    !(c.is_ascii_alphanumeric() || c == '_' || c == '#' || (c == '-' && index == 1))
+                })
+                .map_or(rest.len(), |(index, _)| index);
+            if end == 0 {
+                let unexpected = rest.chars().next().unwrap();
+                return Err(format!("unexpected {} in condition", unexpected));
+            }
+            tokens.push(Token::Word(rest[..end].to_string()));
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+//deep enough for any condition typed by hand, shallow enough for the stack
+const MAX_DEPTH: usize = 64;
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    //unary expressions being parsed, each of which may recurse
+    depth: usize,
+    symbols: Option<&'a SymbolTable>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or("condition ends unexpectedly")?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Symbol(found)) if *found == symbol) {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            Err(format!("expected {} in condition", symbol))
+        }
+    }
+
+    fn or(&mut self) -> Result<Expression, String> {
+        let mut left = self.and()?;
+        while self.eat("||") {
+            left = Expression::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expression, String> {
+        let mut left = self.comparison()?;
+        while self.eat("&&") {
+            left = Expression::And(Box::new(left), Box::new(self.comparison()?));
+        }
+        Ok(left)
+    }
+
+    fn comparison(&mut self) -> Result<Expression, String> {
+        let left = self.unary()?;
+        let comparison = match self.peek() {
+            Some(Token::Symbol("==")) => Comparison::Equal,
+            Some(Token::Symbol("!=")) => Comparison::NotEqual,
+            Some(Token::Symbol("<")) => Comparison::Less,
+            Some(Token::Symbol("<=")) => Comparison::LessOrEqual,
+            Some(Token::Symbol(">")) => Comparison::Greater,
+            Some(Token::Symbol(">=")) => Comparison::GreaterOrEqual,
+            _ => return Ok(left),
+        };
+        self.position += 1;
+        let right = self.unary()?;
+        Ok(Expression::Compare(
+            Box::new(left),
+            comparison,
+            Box::new(right),
+        ))
+    }
+
+    fn unary(&mut self) -> Result<Expression, String> {
+        if self.depth == MAX_DEPTH {
+            return Err("condition is nested too deeply".to_string());
+        }
+        self.depth += 1;
+        let expression = self.nested();
+        self.depth -= 1;
+        expression
+    }
+
+    fn nested(&mut self) -> Result<Expression, String> {
+        match self.next()? {
+            Token::Symbol("!") => Ok(Expression::Not(Box::new(self.unary()?))),
+            Token::Symbol("(") => {
+                let expression = self.or()?;
+                self.expect(")")?;
+                Ok(expression)
+            }
+            Token::Word(word) if word.eq_ignore_ascii_case("mem") => {
+                self.expect("[")?;
+                let address = self.or()?;
+                self.expect("]")?;
+                Ok(Expression::Memory(Box::new(address)))
+            }
+            Token::Word(word) => self.operand(&word),
+            Token::Symbol(symbol) => Err(format!("unexpected {} in condition", symbol)),
+        }
+    }
+
+    fn operand(&self, word: &str) -> Result<Expression, String> {
+        let expression = match word.to_ascii_uppercase().as_str() {
+            "PC" => Expression::Register(RegisterName::Pc),
+            "PSR" => Expression::Register(RegisterName::Psr),
+            "N" => Expression::Flag(0b100),
+            "Z" => Expression::Flag(0b010),
+            "P" => Expression::Flag(0b001),
+            name => match name.strip_prefix('R').map(str::parse::<u16>) {
+                Some(Ok(index)) if index < 8 => {
+                    Expression::Register(RegisterName::from_field(index))
+                }
+                _ => {
+                    let value = parse_word(word)
+                        .or_else(|| self.symbols.and_then(|symbols| symbols.get(word)))
+                        .ok_or(format!("unknown operand {} in condition", word))?;
+                    Expression::Constant(value)
+                }
+            },
+        };
+        Ok(expression)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn holds(source: &str, machine: &Machine) -> bool {
+        Condition::parse(source, None).unwrap().evaluate(machine)
+    }
+
+    #[test]
+    fn it_evaluates_registers_memory_and_condition_codes() {
+        let mut machine = Machine::empty();
+        machine
+            .registers_mut()
+            .update_by_name(RegisterName::R0, 0x41);
+        machine.registers_mut().set_psr(0x0004);
+        machine.memory_mut()[0x4000] = 4;
+        assert!(holds("R0 == x41 && mem[x4000] > 3", &machine));
+        assert!(!holds("r0 != #65 || mem[x4000] <= 3", &machine));
+        assert!(holds("N && !Z && !(P)", &machine));
+        assert!(holds("mem[R0] == 0 && PSR == 4 && PC == 0", &machine));
+        assert!(holds("xFFFF < 0 && #-1 == xFFFF", &machine));
+    }
+
+    #[test]
+    fn it_reads_the_mcr_and_device_registers() {
+        let machine = Machine::empty().start();
+        assert!(holds("mem[xFFFE] == x8000", &machine));
+        assert!(holds("mem[xFE04] == x8000 && mem[xFE00] == 0", &machine));
+    }
+
+    #[test]
+    fn it_resolves_labels() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert("COUNT", 0x4000);
+        let mut machine = Machine::empty();
+        machine.memory_mut()[0x4000] = 2;
+        let condition = Condition::parse("mem[COUNT] >= 2", Some(&symbols)).unwrap();
+        assert!(condition.evaluate(&machine));
+        assert_eq!(condition.to_string(), "mem[COUNT] >= 2");
+    }
+
+    #[test]
+    fn it_rejects_malformed_conditions() {
+        let deep = "(".repeat(100_000);
+        for source in [
+            "",
+            deep.as_str(),
+            "R0 ==",
+            "R8 == 1",
+            "mem[x4000",
+            "(R0",
+            "R0 R1",
+            "R0 = 1",
+            "R0 == é",
+        ]
+        .iter()
+        {
+            assert!(Condition::parse(source, None).is_err(), "{}", source);
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::rc::Rc;
+#[cfg(unix)]
+use std::sync::OnceLock;
+
+/// Character I/O used by the trap routines.
+pub trait Console {
+    /// Blocks until a character is available, returning `None` once input is exhausted.
+    fn read_char(&mut self) -> Option<u8>;
+    /// Returns a character if one is ready, without blocking.
+    fn poll_char(&mut self) -> Option<u8>;
+    fn write_char(&mut self, c: u8);
+    fn flush(&mut self) {}
+}
+
+/// A console backed by the host's stdin and stdout. Input is read straight from
+/// the file descriptor, bypassing `io::stdin()`'s buffer, so that polling sees
+/// exactly what has been typed.
+pub struct StdConsole;
+
+impl Console for StdConsole {
+    #[cfg(unix)]
+    fn read_char(&mut self) -> Option<u8> {
+        let mut c = 0u8;
+        let read = unsafe { libc::read(libc::STDIN_FILENO, &mut c as *mut u8 as *mut _, 1) };
+        if read == 1 {
+            Some(c)
+        } else {
+            None
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn read_char(&mut self) -> Option<u8> {
+        use std::io::Read;
+        let mut buffer = [0; 1];
+        match io::stdin().read(&mut buffer) {
+            Ok(1) => Some(buffer[0]),
+            _ => None,
+        }
+    }
+
+    #[cfg(unix)]
+    fn poll_char(&mut self) -> Option<u8> {
+        let mut fd = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ready = unsafe { libc::poll(&mut fd, 1, 0) };
+        if ready == 1 && fd.revents & libc::POLLIN != 0 {
+            self.read_char()
+        } else {
+            None
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn poll_char(&mut self) -> Option<u8> {
+        self.read_char()
+    }
+
+    fn write_char(&mut self, c: u8) {
+        let _ = io::stdout().write_all(&[c]);
+    }
+
+    fn flush(&mut self) {
+        let _ = io::stdout().flush();
+    }
+}
+
+#[cfg(unix)]
+static ORIGINAL_TERMIOS: OnceLock<libc::termios> = OnceLock::new();
+
+/// Puts the terminal on stdin into raw mode, so keys are delivered as they are
+/// typed and without echo, until dropped. An interrupt also restores it.
+#[cfg(unix)]
+pub struct RawMode {
+    original: libc::termios,
+    //the SIGINT handler to put back when raw mode ends
+    previous_handler: libc::sighandler_t,
+}
+
+#[cfg(unix)]
+impl RawMode {
+    /// Returns `None` if stdin is not a terminal.
+    pub fn enable() -> Option<RawMode> {
+        let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } != 0 {
+            return None;
+        }
+        let original = termios;
+        ORIGINAL_TERMIOS.get_or_init(|| original);
+        termios.c_lflag &= !(libc::ICANON | libc::ECHO);
+        termios.c_cc[libc::VMIN] = 1;
+        termios.c_cc[libc::VTIME] = 0;
+        let previous_handler = unsafe {
+            let previous = libc::signal(
+                libc::SIGINT,
+                restore_and_exit as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            );
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios);
+            previous
+        };
+        Some(RawMode {
+            original,
+            previous_handler,
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+            if self.previous_handler != libc::SIG_ERR {
+                libc::signal(libc::SIGINT, self.previous_handler);
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn restore_and_exit(signal: libc::c_int) {
+    if let Some(original) = ORIGINAL_TERMIOS.get() {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, original);
+        }
+    }
+    unsafe { libc::_exit(128 + signal) }
+}
+
+#[derive(Default)]
+struct Buffers {
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+}
+
+/// An in-memory console. Clones share the same buffers, so a handle kept by the
+/// caller can feed input to and inspect output from a machine that owns another.
+#[derive(Clone, Default)]
+pub struct BufferConsole {
+    buffers: Rc<RefCell<Buffers>>,
+}
+
+impl BufferConsole {
+    pub fn new(input: &[u8]) -> BufferConsole {
+        let console = BufferConsole::default();
+        console.push_input(input);
+        console
+    }
+
+    pub fn push_input(&self, input: &[u8]) {
+        self.buffers.borrow_mut().input.extend(input);
+    }
+
+    pub fn output(&self) -> Vec<u8> {
+        self.buffers.borrow().output.clone()
+    }
+
+    pub fn output_string(&self) -> String {
+        String::from_utf8_lossy(&self.buffers.borrow().output).into_owned()
+    }
+}
+
+impl Console for BufferConsole {
+    fn read_char(&mut self) -> Option<u8> {
+        self.buffers.borrow_mut().input.pop_front()
+    }
+
+    fn poll_char(&mut self) -> Option<u8> {
+        self.read_char()
+    }
+
+    fn write_char(&mut self, c: u8) {
+        self.buffers.borrow_mut().output.push(c);
+    }
+}
@@ -0,0 +1,732 @@
+use std::fmt::Write;
+use std::ops::RangeInclusive;
+
+use crate::condition::Condition;
+use crate::disassembler::disassemble_memory;
+use crate::history::History;
+use crate::instruction::Instruction;
+use crate::machine::{Breakpoint, Machine, StopReason, WatchKind};
+use crate::registers::RegisterName;
+use crate::snapshot::Snapshot;
+use crate::symbols::SymbolTable;
+use crate::util::parse_word;
+
+pub const HELP: &str = "\
+step [N]             execute N instructions (s)
+next [N]             step, running subroutines and traps to completion (n)
+finish               run until the current subroutine returns
+continue             run until a breakpoint, halt or fault (c)
+record [N|stop]      record the last N instructions for reverse execution, or stop
+reverse-step [N]     undo N recorded instructions (rs)
+reverse-continue     undo instructions back to a breakpoint or watchpoint (rc)
+break [ADDR [if COND]]
+                     set a breakpoint, or list them (b)
+condition ADDR [COND]
+                     make a breakpoint conditional, or unconditional
+ignore ADDR N        skip the next N hits of a breakpoint
+delete ADDR          remove a breakpoint (d)
+watch [RANGE]        stop after writes to RANGE, or list watchpoints
+rwatch RANGE         stop after reads of RANGE
+awatch RANGE         stop after any access to RANGE
+unwatch RANGE        remove the watchpoints on RANGE
+registers            print the registers (r)
+x/N ADDR             examine N words of memory
+set REG VALUE        set R0-R7, PC or PSR
+set ADDR VALUE       set a word of memory
+disassemble [ADDR [N]]
+                     disassemble N instructions at ADDR, or around the PC (dis)
+save FILE            save a snapshot of the machine
+restore FILE         restore a snapshot saved with save
+quit                 leave the debugger (q)
+Addresses may be labels from the symbol table. A RANGE is ADDR or ADDR..ADDR.
+Conditions compare R0-R7, PC, PSR, N, Z, P, mem[ADDR] and numbers with == !=
+< <= > >=, combined with && || ! and parentheses, e.g. R0 == x41 && mem[x4000] > 3.
+";
+
+/// A command-driven debugger around a machine. Each command returns the text
+/// to show for it.
+pub struct Debugger {
+    machine: Machine,
+    symbols: SymbolTable,
+}
+
+impl Debugger {
+    pub fn new(machine: Machine, symbols: SymbolTable) -> Debugger {
+        Debugger { machine, symbols }
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    pub fn machine_mut(&mut self) -> &mut Machine {
+        &mut self.machine
+    }
+
+    /// Runs one command line. Errors are messages about the command itself.
+    pub fn execute(&mut self, line: &str) -> Result<String, String> {
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => return Ok(String::new()),
+        };
+        let args: Vec<&str> = words.collect();
+        match command {
+            "step" | "s" => {
+                let count = self.count(args.first(), 1)?;
+                Ok(self.step(count))
+            }
+            "next" | "n" => {
+                let count = self.count(args.first(), 1)?;
+                Ok(self.next(count))
+            }
+            "finish" => Ok(self.finish()),
+            "continue" | "c" => Ok(self.resume()),
+            "record" => match args.first() {
+                Some(&"stop") => {
+                    self.machine.stop_recording();
+                    Ok("recording stopped\n".to_string())
+                }
+                limit => {
+                    let limit = match limit {
+                        Some(limit) => limit
+                            .parse()
+                            .map_err(|_| format!("invalid limit {}", limit))?,
+                        None => History::DEFAULT_LIMIT,
+                    };
+                    self.machine.record_history(limit);
+                    Ok(format!("recording up to {} instructions\n", limit))
+                }
+            },
+            "reverse-step" | "rs" => {
+                let count = self.count(args.first(), 1)?;
+                self.recording()?;
+                Ok(self.reverse_step(count))
+            }
+            "reverse-continue" | "rc" => {
+                self.recording()?;
+                let stop = self.machine.run_backward();
+                Ok(self.report(Some(stop)))
+            }
+            "break" | "b" => match args.as_slice() {
+                [] => Ok(self.list_breakpoints()),
+                [address] => {
+                    let address = self.address(address)?;
+                    self.machine.add_breakpoint(address);
+                    Ok(format!("breakpoint at {}\n", self.describe(address)))
+                }
+                [address, "if", condition @ ..] => {
+                    let address = self.address(address)?;
+                    let condition = self.condition(condition)?;
+                    self.machine.add_breakpoint(address);
+                    self.breakpoint(address)?.condition = condition;
+                    Ok(format!("breakpoint at {}\n", self.describe(address)))
+                }
+                _ => Err("break expects an address and an optional if CONDITION".to_string()),
+            },
+            "condition" => {
+                let (address, condition) =
+                    args.split_first().ok_or("condition expects an address")?;
+                let address = self.address(address)?;
+                let condition = self.condition(condition)?;
+                self.breakpoint(address)?.condition = condition;
+                Ok(String::new())
+            }
+            "ignore" => match args.as_slice() {
+                [address, count] => {
+                    let address = self.address(address)?;
+                    let count = self.count(Some(count), 0)? as u32;
+                    let breakpoint = self.breakpoint(address)?;
+                    breakpoint.ignore_count = breakpoint.hits + count;
+                    Ok(String::new())
+                }
+                _ => Err("ignore expects an address and a count".to_string()),
+            },
+            "delete" | "d" => {
+                let address = self.address(args.first().ok_or("delete expects an address")?)?;
+                if self.machine.remove_breakpoint(address) {
+                    Ok(String::new())
+                } else {
+                    Err(format!("no breakpoint at {}", self.describe(address)))
+                }
+            }
+            "watch" if args.is_empty() => Ok(self.list_watchpoints()),
+            "watch" | "rwatch" | "awatch" => {
+                let range = self.range(args.first().ok_or("expected an address range")?)?;
+                let kind = match command {
+                    "watch" => WatchKind::Write,
+                    "rwatch" => WatchKind::Read,
+                    _ => WatchKind::Access,
+                };
+                self.machine.add_watchpoint(range.clone(), kind);
+                Ok(format!("watchpoint on {}\n", self.describe_range(&range)))
+            }
+            "unwatch" => {
+                let range = self.range(args.first().ok_or("unwatch expects an address range")?)?;
+                if self.machine.remove_watchpoint(&range) {
+                    Ok(String::new())
+                } else {
+                    Err(format!("no watchpoint on {}", self.describe_range(&range)))
+                }
+            }
+            "registers" | "r" => Ok(self.registers()),
+            "set" => match args.as_slice() {
+                [target, value] => self.set(target, value),
+                _ => Err("set expects a register or address and a value".to_string()),
+            },
+            "disassemble" | "dis" => self.disassemble(&args),
+            "save" => {
+                let path = args.first().ok_or("save expects a file")?;
+                self.machine
+                    .snapshot()
+                    .write(path)
+                    .map_err(|error| format!("{}: {}", path, error))?;
+                Ok(format!("saved to {}\n", path))
+            }
+            "restore" => {
+                let path = args.first().ok_or("restore expects a file")?;
+                Snapshot::read(path)
+                    .and_then(|snapshot| self.machine.restore(&snapshot))
+                    .map_err(|error| format!("{}: {}", path, error))?;
+                Ok(self.report(None))
+            }
+            "help" | "h" => Ok(HELP.to_string()),
+            examine if examine == "x" || examine.starts_with("x/") => {
+                let count = self.count(examine.strip_prefix("x/").as_ref(), 1)?;
+                let address = self.address(args.first().ok_or("x expects an address")?)?;
+                Ok(self.examine(address, count))
+            }
+            unknown => Err(format!("unknown command {}; try help", unknown)),
+        }
+    }
+
+    fn count(&self, arg: Option<&&str>, default: u16) -> Result<u16, String> {
+        match arg {
+            //a plain decimal count, so a typo such as #-1 is not 65535 steps
+            Some(arg) if arg.bytes().all(|byte| byte.is_ascii_digit()) => {
+                arg.parse().map_err(|_| format!("invalid count {}", arg))
+            }
+            Some(arg) => Err(format!("invalid count {}", arg)),
+            None => Ok(default),
+        }
+    }
+
+    /// Resolves a label or a number to an address.
+    fn address(&self, arg: &str) -> Result<u16, String> {
+        self.symbols
+            .get(arg)
+            .or_else(|| parse_word(arg))
+            .ok_or(format!("unknown label or address {}", arg))
+    }
+
+    fn breakpoint(&mut self, address: u16) -> Result<&mut Breakpoint, String> {
+        let description = self.describe(address);
+        self.machine
+            .breakpoint_mut(address)
+            .ok_or(format!("no breakpoint at {}", description))
+    }
+
+    /// Parses the words of a condition; none makes a breakpoint unconditional.
+    fn condition(&self, words: &[&str]) -> Result<Option<Condition>, String> {
+        if words.is_empty() {
+            return Ok(None);
+        }
+        Condition::parse(&words.join(" "), Some(&self.symbols)).map(Some)
+    }
+
+    fn range(&self, arg: &str) -> Result<RangeInclusive<u16>, String> {
+        let (start, end) = match arg.split_once("..") {
+            Some((start, end)) => (self.address(start)?, self.address(end)?),
+            None => (self.address(arg)?, self.address(arg)?),
+        };
+        if start > end {
+            return Err(format!("empty range {}", arg));
+        }
+        Ok(start..=end)
+    }
+
+    fn describe_range(&self, range: &RangeInclusive<u16>) -> String {
+        if range.start() == range.end() {
+            self.describe(*range.start())
+        } else {
+            format!(
+                "{}..{}",
+                self.describe(*range.start()),
+                self.describe(*range.end())
+            )
+        }
+    }
+
+    fn describe(&self, address: u16) -> String {
+        match self.symbols.label_at(address) {
+            Some(label) => format!("x{:04X} ({})", address, label),
+            None => format!("x{:04X}", address),
+        }
+    }
+
+    fn current_instruction(&self) -> Instruction {
+        let pc = self.machine.registers().get_pc();
+        Instruction::decode(self.machine.memory().get(pc as usize).copied().unwrap_or(0))
+    }
+
+    /// Steps once, unless the machine has stopped or, when `check_breakpoint`
+    /// is set, the PC is at a breakpoint.
+    fn advance(&mut self, check_breakpoint: bool) -> Option<StopReason> {
+        if !self.machine.is_running() {
+            return Some(StopReason::Halted);
+        }
+        let pc = self.machine.registers().get_pc();
+        if check_breakpoint && self.machine.hit_breakpoint() {
+            return Some(StopReason::Breakpoint(pc));
+        }
+        match self.machine.step() {
+            Ok(()) => self.machine.take_watch_hit(),
+            Err(error) => Some(StopReason::Fault(error)),
+        }
+    }
+
+    fn step(&mut self, count: u16) -> String {
+        for _ in 0..count {
+            if let Some(stop) = self.advance(false) {
+                return self.report(Some(stop));
+            }
+        }
+        self.report(None)
+    }
+
+    fn next(&mut self, count: u16) -> String {
+        for _ in 0..count {
+            let pc = self.machine.registers().get_pc();
+            let is_call = matches!(
+                self.current_instruction(),
+                Instruction::Jsr { .. } | Instruction::Jsrr { .. } | Instruction::Trap { .. }
+            );
+            if let Some(stop) = self.advance(false) {
+                return self.report(Some(stop));
+            }
+            let return_address = pc.wrapping_add(1);
+            while is_call && self.machine.registers().get_pc() != return_address {
+                if let Some(stop) = self.advance(true) {
+                    return self.report(Some(stop));
+                }
+            }
+        }
+        self.report(None)
+    }
+
+    /// Runs until a RET returns from the current subroutine, counting the
+    /// calls made on the way.
+    fn finish(&mut self) -> String {
+        let mut depth = 0;
+        let mut first = true;
+        loop {
+            let pc = self.machine.registers().get_pc();
+            let instruction = self.current_instruction();
+            if let Some(stop) = self.advance(!first) {
+                return self.report(Some(stop));
+            }
+            first = false;
+            match instruction {
+                Instruction::Jmp {
+                    base: RegisterName::R7,
+                } if depth == 0 => return self.report(None),
+                Instruction::Jmp {
+                    base: RegisterName::R7,
+                } => depth -= 1,
+                Instruction::Jsr { .. } | Instruction::Jsrr { .. } | Instruction::Trap { .. }
+                    if self.machine.registers().get_pc() != pc.wrapping_add(1) =>
+                {
+                    depth += 1
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn recording(&self) -> Result<(), String> {
+        match self.machine.history() {
+            Some(_) => Ok(()),
+            None => Err("not recording; use record first".to_string()),
+        }
+    }
+
+    fn reverse_step(&mut self, count: u16) -> String {
+        for _ in 0..count {
+            if !self.machine.step_back() {
+                return self.report(Some(StopReason::HistoryStart));
+            }
+        }
+        self.report(None)
+    }
+
+    /// Continues past the current instruction, even if it has a breakpoint.
+    fn resume(&mut self) -> String {
+        if let Some(stop) = self.advance(false) {
+            return self.report(Some(stop));
+        }
+        let stop = loop {
+            if let Some(stop) = self.advance(true) {
+                break stop;
+            }
+        };
+        self.report(Some(stop))
+    }
+
+    /// Describes why execution stopped, if it did, and the next instruction.
+    fn report(&self, stop: Option<StopReason>) -> String {
+        let mut text = String::new();
+        match stop {
+            Some(StopReason::Halted) => return "halted\n".to_string(),
+            Some(StopReason::Breakpoint(address)) => {
+                writeln!(text, "breakpoint at {}", self.describe(address)).unwrap()
+            }
+            Some(StopReason::Fault(error)) => writeln!(text, "fault: {}", error).unwrap(),
+            Some(StopReason::Watchpoint {
+                address,
+                pc,
+                old,
+                new,
+            }) => writeln!(
+                text,
+                "watchpoint at {}: x{:04X} -> x{:04X} by x{:04X}",
+                self.describe(address),
+                old,
+                new,
+                pc
+            )
+            .unwrap(),
+            Some(StopReason::HistoryStart) => {
+                writeln!(text, "reached the start of the recorded history").unwrap()
+            }
+            Some(StopReason::StepLimit) | None => {}
+        }
+        let pc = self.machine.registers().get_pc();
+        for line in disassemble_memory(&self.machine, pc..=pc, Some(&self.symbols)) {
+            writeln!(text, "=> {}", line).unwrap();
+        }
+        text
+    }
+
+    fn list_breakpoints(&self) -> String {
+        let mut addresses: Vec<u16> = self.machine.breakpoints().copied().collect();
+        addresses.sort_unstable();
+        if addresses.is_empty() {
+            return "no breakpoints\n".to_string();
+        }
+        let mut text = String::new();
+        for address in addresses {
+            write!(text, "breakpoint at {}", self.describe(address)).unwrap();
+            if let Some(breakpoint) = self.machine.breakpoint(address) {
+                if let Some(condition) = &breakpoint.condition {
+                    write!(text, " if {}", condition).unwrap();
+                }
+                write!(text, ", hit count {}", breakpoint.hits).unwrap();
+                if breakpoint.ignore_count > breakpoint.hits {
+                    let remaining = breakpoint.ignore_count - breakpoint.hits;
+                    write!(text, ", ignoring the next {}", remaining).unwrap();
+                }
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    fn list_watchpoints(&self) -> String {
+        let lines: String = self
+            .machine
+            .watchpoints()
+            .map(|(range, kind)| {
+                let kind = match kind {
+                    WatchKind::Read => "read",
+                    WatchKind::Write => "write",
+                    WatchKind::Access => "access",
+                };
+                format!("{} watchpoint on {}\n", kind, self.describe_range(range))
+            })
+            .collect();
+        if lines.is_empty() {
+            "no watchpoints\n".to_string()
+        } else {
+            lines
+        }
+    }
+
+    fn registers(&self) -> String {
+        let registers = self.machine.registers();
+        let mut text = String::new();
+        for index in 0..8 {
+            let value = registers.get_by_name(RegisterName::from_field(index));
+            let separator = if index % 4 == 3 { "\n" } else { "  " };
+            write!(text, "R{} x{:04X}{}", index, value, separator).unwrap();
+        }
+        let psr = registers.get_psr();
+        let flags: String = [(0b100, 'n'), (0b010, 'z'), (0b001, 'p')]
+            .iter()
+            .filter(|(bit, _)| psr & bit != 0)
+            .map(|(_, flag)| *flag)
+            .collect();
+        writeln!(
+            text,
+            "PC x{:04X}  PSR x{:04X}  CC {}  {} mode, priority {}",
+            registers.get_pc(),
+            psr,
+            flags,
+            if registers.is_user_mode() {
+                "user"
+            } else {
+                "supervisor"
+            },
+            registers.priority()
+        )
+        .unwrap();
+        text
+    }
+
+    fn set(&mut self, target: &str, value: &str) -> Result<String, String> {
+        let value = self
+            .symbols
+            .get(value)
+            .or_else(|| parse_word(value))
+            .ok_or(format!("invalid value {}", value))?;
+        let register = match target.to_ascii_uppercase().as_str() {
+            "PC" => Some(RegisterName::Pc),
+            "PSR" => Some(RegisterName::Psr),
+            name => match name.strip_prefix('R').map(str::parse::<u16>) {
+                Some(Ok(index)) if index < 8 => Some(RegisterName::from_field(index)),
+                _ => None,
+            },
+        };
+        match register {
+            Some(register) => {
+                self.machine.registers_mut().update_by_name(register, value);
+                //reversing past the change would reach a state that never existed
+                self.machine.clear_history();
+            }
+            None => {
+                let address = self.address(target)?;
+                self.machine.poke(address, value);
+            }
+        }
+        Ok(String::new())
+    }
+
+    fn examine(&self, address: u16, count: u16) -> String {
+        let mut text = String::new();
+        for index in 0..count {
+            let current = address.wrapping_add(index);
+            if index % 8 == 0 {
+                if index > 0 {
+                    text.push('\n');
+                }
+                write!(text, "x{:04X}:", current).unwrap();
+            }
+            write!(text, " x{:04X}", self.machine.peek(current)).unwrap();
+        }
+        text.push('\n');
+        text
+    }
+
+    fn disassemble(&self, args: &[&str]) -> Result<String, String> {
+        let pc = self.machine.registers().get_pc();
+        let (start, count) = match args {
+            [] => (pc.saturating_sub(3), 8),
+            [address] => (self.address(address)?, 8),
+            [address, count] => (self.address(address)?, self.count(Some(count), 8)?),
+            _ => return Err("disassemble expects an address and a count".to_string()),
+        };
+        if count == 0 {
+            return Ok(String::new());
+        }
+        let end = start.saturating_add(count - 1);
+        let lines = disassemble_memory(&self.machine, start..=end, Some(&self.symbols));
+        Ok(lines
+            .iter()
+            .map(|line| {
+                let marker = if line.address == pc { "=>" } else { "  " };
+                format!("{} {}\n", marker, line)
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::console::BufferConsole;
+
+    const PROGRAM: &str = ".ORIG x3000
+                 AND R1, R1, #0
+                 ADD R1, R1, #3
+        LOOP     JSR DECREMENT
+                 BRp LOOP
+                 OUT
+                 HALT
+        DECREMENT ADD R1, R1, #-1
+                 RET
+        .END";
+
+    fn debugger() -> (Debugger, BufferConsole) {
+        let console = BufferConsole::new(b"");
+        let program = assemble(PROGRAM).unwrap();
+        let mut machine = Machine::empty()
+            .with_console(Box::new(console.clone()))
+            .start();
+        machine.load_object(&program.object);
+        (Debugger::new(machine, program.symbols), console)
+    }
+
+    fn r1(debugger: &Debugger) -> u16 {
+        debugger.machine().registers().get_by_name(RegisterName::R1)
+    }
+
+    #[test]
+    fn it_steps_and_reports_the_next_instruction() {
+        let (mut debugger, _) = debugger();
+        let output = debugger.execute("step 2").unwrap();
+        assert_eq!(output, "=> LOOP             x3002  x4803  JSR DECREMENT\n");
+        assert_eq!(r1(&debugger), 3);
+        debugger.execute("s").unwrap();
+        assert_eq!(debugger.machine().registers().get_pc(), 0x3006);
+    }
+
+    #[test]
+    fn it_steps_over_calls_and_finishes_subroutines() {
+        let (mut debugger, _) = debugger();
+        debugger.execute("next 3").unwrap();
+        assert_eq!(debugger.machine().registers().get_pc(), 0x3003);
+        assert_eq!(r1(&debugger), 2);
+
+        debugger.execute("step 2").unwrap();
+        debugger.execute("finish").unwrap();
+        assert_eq!(debugger.machine().registers().get_pc(), 0x3003);
+        assert_eq!(r1(&debugger), 1);
+    }
+
+    #[test]
+    fn it_continues_to_breakpoints_and_past_them() {
+        let (mut debugger, console) = debugger();
+        assert_eq!(
+            debugger.execute("break DECREMENT").unwrap(),
+            "breakpoint at x3006 (DECREMENT)\n"
+        );
+        let output = debugger.execute("continue").unwrap();
+        assert!(output.starts_with("breakpoint at x3006 (DECREMENT)\n=> DECREMENT"));
+        debugger.execute("c").unwrap();
+        assert_eq!(r1(&debugger), 2);
+        debugger.execute("delete x3006").unwrap();
+        assert_eq!(debugger.execute("break").unwrap(), "no breakpoints\n");
+        assert_eq!(debugger.execute("c").unwrap(), "halted\n");
+        assert!(console
+            .output_string()
+            .ends_with("--- halting the LC-3 ---\n"));
+        assert!(debugger.execute("delete x3006").is_err());
+    }
+
+    #[test]
+    fn it_stops_at_conditional_breakpoints() {
+        let (mut debugger, _) = debugger();
+        debugger.execute("break DECREMENT if R1 == 2").unwrap();
+        let output = debugger.execute("c").unwrap();
+        assert!(output.starts_with("breakpoint at x3006 (DECREMENT)\n"));
+        assert_eq!(r1(&debugger), 2);
+
+        debugger.execute("condition DECREMENT").unwrap();
+        debugger.execute("ignore DECREMENT 1").unwrap();
+        assert_eq!(
+            debugger.execute("break").unwrap(),
+            "breakpoint at x3006 (DECREMENT), hit count 1, ignoring the next 1\n"
+        );
+        assert_eq!(debugger.execute("c").unwrap(), "halted\n");
+        assert!(debugger.execute("break LOOP if R1 ==").is_err());
+        assert!(debugger.execute("condition LOOP R1 == 1").is_err());
+    }
+
+    #[test]
+    fn it_stops_at_watchpoints() {
+        let (mut debugger, _) = debugger();
+        debugger.execute("watch R1").unwrap_err();
+        debugger.execute("awatch x4000..x4001").unwrap();
+        assert_eq!(
+            debugger.execute("rwatch DECREMENT..x3007").unwrap(),
+            "watchpoint on x3006 (DECREMENT)..x3007\n"
+        );
+        assert_eq!(
+            debugger.execute("watch").unwrap(),
+            "access watchpoint on x4000..x4001\nread watchpoint on x3006 (DECREMENT)..x3007\n"
+        );
+        debugger.execute("unwatch DECREMENT..x3007").unwrap();
+        assert!(debugger.execute("unwatch x4000").is_err());
+
+        //make the ADD at x3001 an ST R1 to x3003
+        debugger.execute("set x3001 x3201").unwrap();
+        debugger.execute("watch x3003").unwrap();
+        let output = debugger.execute("c").unwrap();
+        assert!(output.starts_with("watchpoint at x3003: x03FE -> x0000 by x3001\n"));
+    }
+
+    #[test]
+    fn it_reverses_recorded_execution() {
+        let (mut debugger, _) = debugger();
+        assert!(debugger.execute("rs").is_err());
+        debugger.execute("record").unwrap();
+        debugger.execute("break DECREMENT").unwrap();
+        debugger.execute("c").unwrap();
+        debugger.execute("c").unwrap();
+        assert_eq!(r1(&debugger), 2);
+
+        let output = debugger.execute("rc").unwrap();
+        assert!(output.starts_with("breakpoint at x3006 (DECREMENT)\n"));
+        assert_eq!(r1(&debugger), 3);
+        let output = debugger.execute("reverse-step 4").unwrap();
+        assert!(output.starts_with("reached the start of the recorded history\n"));
+        assert_eq!(debugger.machine().registers().get_pc(), 0x3000);
+        assert_eq!(r1(&debugger), 0);
+    }
+
+    #[test]
+    fn it_forgets_the_history_when_state_is_set() {
+        let (mut debugger, _) = debugger();
+        debugger.execute("record").unwrap();
+        debugger.execute("step 2").unwrap();
+        debugger.execute("set x4000 x1234").unwrap();
+        let output = debugger.execute("reverse-step").unwrap();
+        assert!(output.starts_with("reached the start of the recorded history\n"));
+        assert_eq!(r1(&debugger), 3);
+        assert_eq!(debugger.machine().memory()[0x4000], 0x1234);
+    }
+
+    #[test]
+    fn it_prints_and_sets_registers_and_memory() {
+        let (mut debugger, _) = debugger();
+        debugger.execute("set R1 #-2").unwrap();
+        debugger.execute("set PSR x8001").unwrap();
+        debugger.execute("set x4000 LOOP").unwrap();
+        let registers = debugger.execute("registers").unwrap();
+        assert!(registers.contains("R1 xFFFE"));
+        assert!(registers.contains("PC x3000  PSR x8001  CC p  user mode, priority 0\n"));
+        assert_eq!(
+            debugger.execute("x/3 x3FFF").unwrap(),
+            "x3FFF: x0000 x3002 x0000\n"
+        );
+        assert!(debugger.execute("set R8 1").is_err());
+        assert!(debugger.execute("step #-1").is_err());
+        assert!(debugger.execute("step x10").is_err());
+        assert!(debugger.execute("x/2 NOWHERE").is_err());
+    }
+
+    #[test]
+    fn it_disassembles_around_the_pc() {
+        let (mut debugger, _) = debugger();
+        debugger.execute("step 3").unwrap();
+        let output = debugger.execute("dis").unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert_eq!(lines[0], "                    x3003  x03FE  BRp LOOP");
+        assert_eq!(lines[2], "                    x3005  xF025  HALT");
+        assert_eq!(
+            lines[3],
+            "=> DECREMENT        x3006  x127F  ADD R1, R1, #-1"
+        );
+    }
+}
@@ -0,0 +1,389 @@
+use std::error::Error;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::console::Console;
+
+/// The first address of the I/O page. Devices can only be mapped at or above it.
+pub const IO_PAGE_START: u16 = 0xFE00;
+
+/// A memory-mapped peripheral. Registers are addressed by their offset from the
+/// start of the range the device is mapped at.
+pub trait Device {
+    fn read(&mut self, offset: u16, console: &mut dyn Console) -> u16;
+    fn write(&mut self, offset: u16, value: u16, console: &mut dyn Console);
+    /// A register's value without the side effects of `read`, for debuggers.
+    fn peek(&self, _offset: u16) -> u16 {
+        0
+    }
+    /// Called once per executed instruction.
+    fn tick(&mut self, _console: &mut dyn Console) {}
+    /// The interrupt this device is requesting, if any. It stays pending until
+    /// the device withdraws it, normally when its service routine accesses it.
+    fn interrupt(&self) -> Option<Interrupt> {
+        None
+    }
+    /// The device's internal state, for snapshots.
+    fn save(&self) -> Vec<u16> {
+        Vec::new()
+    }
+    /// Restores state produced by `save`, returning false if it is not valid.
+    fn restore(&mut self, state: &[u16]) -> bool {
+        state.is_empty()
+    }
+}
+
+/// An interrupt request: the vector to dispatch through (x80-xFF by convention)
+/// and the priority level, PL0-PL7, it must exceed to be taken.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Interrupt {
+    vector: u8,
+    priority: u16,
+}
+
+impl Interrupt {
+    /// Panics if `priority` is not a level from 0 to 7.
+    pub fn new(vector: u8, priority: u16) -> Interrupt {
+        assert!(
+            priority <= 7,
+            "interrupt priority {} is not PL0-PL7",
+            priority
+        );
+        Interrupt { vector, priority }
+    }
+
+    pub fn vector(&self) -> u8 {
+        self.vector
+    }
+
+    pub fn priority(&self) -> u16 {
+        self.priority
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BusError {
+    OutsideIoPage { start: u16, end: u16 },
+    Overlap { start: u16, end: u16 },
+}
+
+impl fmt::Display for BusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BusError::OutsideIoPage { start, end } => write!(
+                f,
+                "x{:04X}-x{:04X} is outside the I/O page (x{:04X}-xFFFF)",
+                start, end, IO_PAGE_START
+            ),
+            BusError::Overlap { start, end } => {
+                write!(f, "x{:04X}-x{:04X} overlaps a mapped device", start, end)
+            }
+        }
+    }
+}
+
+impl Error for BusError {}
+
+/// Routes accesses in the I/O page to the devices mapped there.
+#[derive(Default)]
+pub struct DeviceBus {
+    devices: Vec<(RangeInclusive<u16>, Box<dyn Device>)>,
+}
+
+impl DeviceBus {
+    pub fn new() -> DeviceBus {
+        DeviceBus::default()
+    }
+
+    pub fn map(
+        &mut self,
+        range: RangeInclusive<u16>,
+        device: Box<dyn Device>,
+    ) -> Result<(), BusError> {
+        let (start, end) = (*range.start(), *range.end());
+        if start < IO_PAGE_START || start > end {
+            return Err(BusError::OutsideIoPage { start, end });
+        }
+        let overlaps = self
+            .devices
+            .iter()
+            .any(|(mapped, _)| start <= *mapped.end() && *mapped.start() <= end);
+        if overlaps {
+            return Err(BusError::Overlap { start, end });
+        }
+        self.devices.push((range, device));
+        Ok(())
+    }
+
+    /// Returns `None` if no device is mapped at `address`.
+    pub fn read(&mut self, address: u16, console: &mut dyn Console) -> Option<u16> {
+        self.devices
+            .iter_mut()
+            .find(|(range, _)| range.contains(&address))
+            .map(|(range, device)| device.read(address - range.start(), console))
+    }
+
+    /// Like `read`, but without side effects.
+    pub fn peek(&self, address: u16) -> Option<u16> {
+        self.devices
+            .iter()
+            .find(|(range, _)| range.contains(&address))
+            .map(|(range, device)| device.peek(address - range.start()))
+    }
+
+    /// Returns false if no device is mapped at `address`.
+    pub fn write(&mut self, address: u16, value: u16, console: &mut dyn Console) -> bool {
+        match self
+            .devices
+            .iter_mut()
+            .find(|(range, _)| range.contains(&address))
+        {
+            Some((range, device)) => {
+                device.write(address - range.start(), value, console);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn tick(&mut self, console: &mut dyn Console) {
+        for (_, device) in self.devices.iter_mut() {
+            device.tick(console);
+        }
+    }
+
+    /// The state of each device, in mapping order.
+    pub fn save(&self) -> Vec<Vec<u16>> {
+        self.devices
+            .iter()
+            .map(|(_, device)| device.save())
+            .collect()
+    }
+
+    /// Restores the states returned by `save`, returning false if they do not
+    /// match the mapped devices.
+    pub fn restore(&mut self, states: &[Vec<u16>]) -> bool {
+        states.len() == self.devices.len()
+            && self
+                .devices
+                .iter_mut()
+                .zip(states)
+                .all(|((_, device), state)| device.restore(state))
+    }
+
+    /// The highest priority interrupt requested by any device. Ties go to the
+    /// device mapped first.
+    pub fn pending_interrupt(&self) -> Option<Interrupt> {
+        self.devices
+            .iter()
+            .filter_map(|(_, device)| device.interrupt())
+            .fold(None, |highest: Option<Interrupt>, request| match highest {
+                Some(highest) if highest.priority >= request.priority => Some(highest),
+                _ => Some(request),
+            })
+    }
+}
+
+/// KBSR and KBDR, fed from the console. Bit 15 of KBSR is set while a key is
+/// waiting in KBDR; reading KBDR consumes it. Setting bit 14 of KBSR enables
+/// the keyboard interrupt, at vector x80 and PL4.
+#[derive(Default)]
+pub struct Keyboard {
+    data: u16,
+    ready: bool,
+    interrupt_enabled: bool,
+}
+
+impl Keyboard {
+    const STATUS: u16 = 0;
+    const DATA: u16 = 2;
+    pub const VECTOR: u8 = 0x80;
+    pub const PRIORITY: u16 = 4;
+
+    fn poll(&mut self, console: &mut dyn Console) {
+        if self.ready {
+            return;
+        }
+        if let Some(c) = console.poll_char() {
+            self.data = c as u16;
+            self.ready = true;
+        }
+    }
+}
+
+impl Device for Keyboard {
+    fn read(&mut self, offset: u16, console: &mut dyn Console) -> u16 {
+        self.poll(console);
+        let value = self.peek(offset);
+        if offset == Keyboard::DATA {
+            self.ready = false;
+        }
+        value
+    }
+
+    //the state as of the last poll, so a key not yet seen is not reported
+    fn peek(&self, offset: u16) -> u16 {
+        match offset {
+            Keyboard::STATUS => {
+                let ready = if self.ready { 0x8000 } else { 0 };
+                let enabled = if self.interrupt_enabled { 0x4000 } else { 0 };
+                ready | enabled
+            }
+            Keyboard::DATA => self.data,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u16, value: u16, _console: &mut dyn Console) {
+        if offset == Keyboard::STATUS {
+            self.interrupt_enabled = value & 0x4000 != 0;
+        }
+    }
+
+    //only poll every instruction when an interrupt could result
+    fn tick(&mut self, console: &mut dyn Console) {
+        if self.interrupt_enabled {
+            self.poll(console);
+        }
+    }
+
+    fn save(&self) -> Vec<u16> {
+        vec![self.data, self.ready as u16, self.interrupt_enabled as u16]
+    }
+
+    fn restore(&mut self, state: &[u16]) -> bool {
+        match *state {
+            [data, ready, interrupt_enabled] => {
+                self.data = data;
+                self.ready = ready != 0;
+                self.interrupt_enabled = interrupt_enabled != 0;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn interrupt(&self) -> Option<Interrupt> {
+        if self.interrupt_enabled && self.ready {
+            Some(Interrupt::new(Keyboard::VECTOR, Keyboard::PRIORITY))
+        } else {
+            None
+        }
+    }
+}
+
+/// DSR and DDR. Output is written synchronously, so the display is always ready.
+pub struct Display;
+
+impl Display {
+    const STATUS: u16 = 0;
+    const DATA: u16 = 2;
+}
+
+impl Device for Display {
+    fn read(&mut self, offset: u16, _console: &mut dyn Console) -> u16 {
+        self.peek(offset)
+    }
+
+    fn peek(&self, offset: u16) -> u16 {
+        match offset {
+            Display::STATUS => 0x8000,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u16, value: u16, console: &mut dyn Console) {
+        if offset == Display::DATA {
+            console.write_char(value as u8);
+            console.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::console::BufferConsole;
+
+    struct Counter {
+        ticks: u16,
+    }
+
+    impl Device for Counter {
+        fn read(&mut self, _offset: u16, _console: &mut dyn Console) -> u16 {
+            self.ticks
+        }
+
+        fn write(&mut self, _offset: u16, value: u16, _console: &mut dyn Console) {
+            self.ticks = value;
+        }
+
+        fn tick(&mut self, _console: &mut dyn Console) {
+            self.ticks += 1;
+        }
+
+        fn interrupt(&self) -> Option<Interrupt> {
+            Some(Interrupt::new(0x81, self.ticks))
+        }
+    }
+
+    #[test]
+    fn it_routes_accesses_by_offset() {
+        let mut console = BufferConsole::new(b"");
+        let mut bus = DeviceBus::new();
+        bus.map(0xFE10..=0xFE11, Box::new(Counter { ticks: 0 }))
+            .unwrap();
+        bus.tick(&mut console);
+        bus.tick(&mut console);
+        assert_eq!(bus.read(0xFE11, &mut console), Some(2));
+        assert!(bus.write(0xFE10, 7, &mut console));
+        assert_eq!(bus.read(0xFE10, &mut console), Some(7));
+        assert_eq!(bus.read(0xFE12, &mut console), None);
+        assert!(!bus.write(0xFE12, 7, &mut console));
+    }
+
+    #[test]
+    fn it_rejects_bad_ranges() {
+        let mut bus = DeviceBus::new();
+        bus.map(0xFE00..=0xFE03, Box::new(Keyboard::default()))
+            .unwrap();
+        assert_eq!(
+            bus.map(0xFE02..=0xFE05, Box::new(Display)),
+            Err(BusError::Overlap {
+                start: 0xFE02,
+                end: 0xFE05
+            })
+        );
+        assert_eq!(
+            bus.map(0x3000..=0x3001, Box::new(Display)),
+            Err(BusError::OutsideIoPage {
+                start: 0x3000,
+                end: 0x3001
+            })
+        );
+    }
+
+    #[test]
+    fn it_picks_the_highest_priority_request() {
+        let mut console = BufferConsole::new(b"k");
+        let mut bus = DeviceBus::new();
+        bus.map(0xFE00..=0xFE03, Box::new(Keyboard::default()))
+            .unwrap();
+        bus.map(0xFE10..=0xFE10, Box::new(Counter { ticks: 2 }))
+            .unwrap();
+        assert_eq!(bus.pending_interrupt().unwrap().vector(), 0x81);
+
+        bus.write(0xFE00, 0x4000, &mut console);
+        bus.tick(&mut console);
+        assert_eq!(bus.read(0xFE00, &mut console), Some(0xC000));
+        assert_eq!(bus.pending_interrupt(), Some(Interrupt::new(0x80, 4)));
+        assert_eq!(bus.read(0xFE02, &mut console), Some(b'k' as u16));
+        assert_eq!(bus.pending_interrupt().unwrap().vector(), 0x81);
+    }
+
+    #[test]
+    #[should_panic(expected = "interrupt priority 9 is not PL0-PL7")]
+    fn it_rejects_priorities_above_pl7() {
+        Interrupt::new(0x81, 9);
+    }
+}
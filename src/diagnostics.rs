@@ -0,0 +1,107 @@
+use std::fmt;
+
+/// A location in source text. Lines and columns count from 1; a length of zero
+/// marks a position rather than a range.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub message: String,
+}
+
+impl Span {
+    pub fn new(line: usize, column: usize, length: usize) -> Span {
+        Span {
+            line,
+            column,
+            length,
+        }
+    }
+}
+
+impl Diagnostic {
+    pub fn new<S: Into<String>>(span: Span, message: S) -> Diagnostic {
+        Diagnostic {
+            span,
+            message: message.into(),
+        }
+    }
+
+    /// Renders the message followed by the offending source line with the span
+    /// underlined, e.g.
+    ///
+    /// ```text
+    /// error: undefined label LOOP
+    ///  --> count.asm:2:4
+    ///   |
+    /// 2 | BR LOOP
+    ///   |    ^^^^
+    /// ```
+    pub fn render(&self, file: &str, source: &str) -> String {
+        let Span {
+            line,
+            column,
+            length,
+        } = self.span;
+        let gutter = " ".repeat(line.to_string().len());
+        let mut rendered = format!(
+            "error: {}\n{}--> {}:{}:{}\n",
+            self.message, gutter, file, line, column
+        );
+        if let Some(text) = source.lines().nth(line.wrapping_sub(1)) {
+            //keep tabs so the carets line up however the terminal expands them
+            let indent: String = text
+                .chars()
+                .take(column.saturating_sub(1))
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            rendered.push_str(&format!("{} |\n", gutter));
+            rendered.push_str(&format!("{} | {}\n", line, text));
+            rendered.push_str(&format!(
+                "{} | {}{}\n",
+                gutter,
+                indent,
+                "^".repeat(length.max(1))
+            ));
+        }
+        rendered
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}",
+            self.span.line, self.span.column, self.message
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_underlines_the_span() {
+        let diagnostic = Diagnostic::new(Span::new(2, 4, 4), "undefined label LOOP");
+        let rendered = diagnostic.render("count.asm", ".ORIG x3000\nBR LOOP\n");
+        assert_eq!(
+            rendered,
+            "error: undefined label LOOP\n --> count.asm:2:4\n  |\n2 | BR LOOP\n  |    ^^^^\n"
+        );
+    }
+
+    #[test]
+    fn it_keeps_tabs_when_aligning_carets() {
+        let diagnostic = Diagnostic::new(Span::new(1, 3, 0), "here");
+        let rendered = diagnostic.render("tabs.asm", "\tX Y");
+        assert!(rendered.ends_with("1 | \tX Y\n  | \t ^\n"));
+    }
+}
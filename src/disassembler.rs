@@ -0,0 +1,220 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::instruction::{Instruction, Operand};
+use crate::loader::ObjectFile;
+use crate::machine::Machine;
+use crate::registers::RegisterName;
+use crate::symbols::SymbolTable;
+use crate::traps::Traps;
+
+/// One disassembled word.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Line {
+    pub address: u16,
+    pub word: u16,
+    /// The label defined at this address, if a symbol table was given.
+    pub label: Option<String>,
+    pub text: String,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<16} x{:04X}  x{:04X}  {}",
+            self.label.as_deref().unwrap_or(""),
+            self.address,
+            self.word,
+            self.text
+        )
+    }
+}
+
+/// Disassembles `word` as if it were located at `address`. PC-relative operands
+/// are shown as absolute addresses, or as labels when `symbols` names them.
+/// Words that are not instructions are shown as `.FILL`.
+pub fn disassemble(word: u16, address: u16, symbols: Option<&SymbolTable>) -> String {
+    let target = |offset: i16| {
+        let target = address.wrapping_add(1).wrapping_add(offset as u16);
+        match symbols.and_then(|symbols| symbols.label_at(target)) {
+            Some(label) => label.to_string(),
+            None => format!("x{:04X}", target),
+        }
+    };
+    let operand = |operand: Operand| match operand {
+        Operand::Register(sr2) => format!("R{}", sr2 as u16),
+        Operand::Immediate(imm5) => format!("#{}", imm5),
+    };
+    match Instruction::decode(word) {
+        Instruction::Br { nzp: 0, .. } => "NOP".to_string(),
+        Instruction::Br { nzp, offset9 } => {
+            let flags: String = [(0b100, 'n'), (0b010, 'z'), (0b001, 'p')]
+                .iter()
+                .filter(|(bit, _)| nzp & bit != 0)
+                .map(|(_, flag)| *flag)
+                .collect();
+            format!("BR{} {}", flags, target(offset9))
+        }
+        Instruction::Add {
+            dr,
+            sr1,
+            operand: source,
+        } => format!("ADD R{}, R{}, {}", dr as u16, sr1 as u16, operand(source)),
+        Instruction::And {
+            dr,
+            sr1,
+            operand: source,
+        } => format!("AND R{}, R{}, {}", dr as u16, sr1 as u16, operand(source)),
+        Instruction::Not { dr, sr } => format!("NOT R{}, R{}", dr as u16, sr as u16),
+        Instruction::Ld { dr, offset9 } => format!("LD R{}, {}", dr as u16, target(offset9)),
+        Instruction::Ldi { dr, offset9 } => format!("LDI R{}, {}", dr as u16, target(offset9)),
+        Instruction::Lea { dr, offset9 } => format!("LEA R{}, {}", dr as u16, target(offset9)),
+        Instruction::St { sr, offset9 } => format!("ST R{}, {}", sr as u16, target(offset9)),
+        Instruction::Sti { sr, offset9 } => format!("STI R{}, {}", sr as u16, target(offset9)),
+        Instruction::Ldr { dr, base, offset6 } => {
+            format!("LDR R{}, R{}, #{}", dr as u16, base as u16, offset6)
+        }
+        Instruction::Str { sr, base, offset6 } => {
+            format!("STR R{}, R{}, #{}", sr as u16, base as u16, offset6)
+        }
+        Instruction::Jmp {
+            base: RegisterName::R7,
+        } => "RET".to_string(),
+        Instruction::Jmp { base } => format!("JMP R{}", base as u16),
+        Instruction::Jsr { offset11 } => format!("JSR {}", target(offset11)),
+        Instruction::Jsrr { base } => format!("JSRR R{}", base as u16),
+        Instruction::Rti => "RTI".to_string(),
+        Instruction::Trap { trapvect8 } => {
+            let maybe_trap: Option<Traps> = num::FromPrimitive::from_u8(trapvect8);
+            match maybe_trap {
+                Some(Traps::Getc) => "GETC".to_string(),
+                Some(Traps::Out) => "OUT".to_string(),
+                Some(Traps::Puts) => "PUTS".to_string(),
+                Some(Traps::In) => "IN".to_string(),
+                Some(Traps::Putsp) => "PUTSP".to_string(),
+                Some(Traps::Halt) => "HALT".to_string(),
+                None => format!("TRAP x{:02X}", trapvect8),
+            }
+        }
+        Instruction::Reserved(_) | Instruction::Nonstandard(_) => format!(".FILL x{:04X}", word),
+    }
+}
+
+/// Disassembles consecutive words starting at `origin`.
+pub fn disassemble_words(origin: u16, words: &[u16], symbols: Option<&SymbolTable>) -> Vec<Line> {
+    words
+        .iter()
+        .enumerate()
+        .map(|(index, &word)| {
+            let address = origin.wrapping_add(index as u16);
+            Line {
+                address,
+                word,
+                label: symbols
+                    .and_then(|symbols| symbols.label_at(address))
+                    .map(str::to_string),
+                text: disassemble(word, address, symbols),
+            }
+        })
+        .collect()
+}
+
+/// Disassembles the memory of `machine` over `range`, without side effects on it.
+pub fn disassemble_memory(
+    machine: &Machine,
+    range: RangeInclusive<u16>,
+    symbols: Option<&SymbolTable>,
+) -> Vec<Line> {
+    let memory = machine.memory();
+    let start = *range.start() as usize;
+    let end = *range.end() as usize;
+    if start > end {
+        return Vec::new();
+    }
+    disassemble_words(*range.start(), &memory[start..=end], symbols)
+}
+
+pub fn disassemble_object(object: &ObjectFile, symbols: Option<&SymbolTable>) -> Vec<Line> {
+    disassemble_words(object.origin, &object.words, symbols)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    #[test]
+    fn it_disassembles_every_instruction() {
+        let cases = [
+            (0x1642, "ADD R3, R1, R2"),
+            (0x103F, "ADD R0, R0, #-1"),
+            (0x5B60, "AND R5, R5, #0"),
+            (0x92BF, "NOT R1, R2"),
+            (0x0FFE, "BRnzp x2FFF"),
+            (0x0401, "BRz x3002"),
+            (0x0000, "NOP"),
+            (0x2403, "LD R2, x3004"),
+            (0xA601, "LDI R3, x3002"),
+            (0x687D, "LDR R4, R1, #-3"),
+            (0xEBF0, "LEA R5, x2FF1"),
+            (0x3C02, "ST R6, x3003"),
+            (0xBE01, "STI R7, x3002"),
+            (0x71A0, "STR R0, R6, #-32"),
+            (0xC080, "JMP R2"),
+            (0xC1C0, "RET"),
+            (0x4C00, "JSR x2C01"),
+            (0x40C0, "JSRR R3"),
+            (0x8000, "RTI"),
+            (0xF040, "TRAP x40"),
+            (0xF025, "HALT"),
+            (0xD123, ".FILL xD123"),
+            (0x92BE, ".FILL x92BE"),
+        ];
+        for &(word, expected) in cases.iter() {
+            assert_eq!(disassemble(word, 0x3000, None), expected);
+        }
+    }
+
+    #[test]
+    fn it_resolves_targets_to_labels() {
+        let program = assemble(
+            ".ORIG x3000
+            LOOP ADD R1, R1, #-1
+                 BRp LOOP
+                 LEA R0, MESSAGE
+                 HALT
+            MESSAGE .FILL x0
+            .END",
+        )
+        .unwrap();
+        let lines = disassemble_object(&program.object, Some(&program.symbols));
+        let text: Vec<&str> = lines.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(
+            text,
+            vec![
+                "ADD R1, R1, #-1",
+                "BRp LOOP",
+                "LEA R0, MESSAGE",
+                "HALT",
+                "NOP"
+            ]
+        );
+        assert_eq!(lines[0].label.as_deref(), Some("LOOP"));
+        assert_eq!(
+            lines[1].to_string(),
+            "                 x3001  x03FE  BRp LOOP"
+        );
+    }
+
+    #[test]
+    fn it_disassembles_a_memory_range() {
+        let mut machine = Machine::empty();
+        machine.write_memory(0x3000, 0xF025).unwrap();
+        let lines = disassemble_memory(&machine, 0x2FFF..=0x3000, None);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].text, "NOP");
+        assert_eq!(lines[1].text, "HALT");
+        assert_eq!(disassemble_memory(&machine, 0xFFFE..=0xFFFF, None).len(), 2);
+    }
+}
@@ -0,0 +1,69 @@
+use std::error::Error;
+use std::fmt;
+
+/// A fault raised while executing an instruction. Every variant carries the
+/// address of the faulting instruction and the instruction word itself.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MachineError {
+    /// Opcode 13, which the ISA reserves; it raises the illegal opcode exception.
+    /// It is the only illegal opcode: every other opcode names an instruction,
+    /// and words with nonstandard unused bits still execute.
+    ReservedOpcode { pc: u16, instruction: u16 },
+    /// A privileged instruction (RTI) executed in user mode.
+    PrivilegeViolation { pc: u16, instruction: u16 },
+    /// A user mode access to system space or the I/O page.
+    AccessViolation {
+        pc: u16,
+        instruction: u16,
+        address: u16,
+    },
+}
+
+impl MachineError {
+    pub fn pc(&self) -> u16 {
+        match *self {
+            MachineError::ReservedOpcode { pc, .. }
+            | MachineError::PrivilegeViolation { pc, .. }
+            | MachineError::AccessViolation { pc, .. } => pc,
+        }
+    }
+
+    pub fn instruction(&self) -> u16 {
+        match *self {
+            MachineError::ReservedOpcode { instruction, .. }
+            | MachineError::PrivilegeViolation { instruction, .. }
+            | MachineError::AccessViolation { instruction, .. } => instruction,
+        }
+    }
+
+    /// The exception vector the ISA raises for this fault.
+    pub fn exception_vector(&self) -> u8 {
+        match self {
+            MachineError::PrivilegeViolation { .. } => 0x00,
+            MachineError::ReservedOpcode { .. } => 0x01,
+            MachineError::AccessViolation { .. } => 0x02,
+        }
+    }
+}
+
+impl fmt::Display for MachineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MachineError::ReservedOpcode { .. } => write!(f, "reserved opcode")?,
+            MachineError::PrivilegeViolation { .. } => write!(f, "privilege mode violation")?,
+            MachineError::AccessViolation { address, .. } => write!(
+                f,
+                "access control violation accessing x{:04X} from user mode",
+                address
+            )?,
+        }
+        write!(
+            f,
+            " at x{:04X} (instruction x{:04X})",
+            self.pc(),
+            self.instruction()
+        )
+    }
+}
+
+impl Error for MachineError {}
@@ -0,0 +1,536 @@
+use std::fmt::Write as _;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::ops::RangeInclusive;
+
+use crate::error::MachineError;
+use crate::machine::{Machine, StopReason, WatchKind};
+use crate::registers::RegisterName;
+
+/// Registers in the order of the `g` packet and the numbers of `p` and `P`.
+const REGISTERS: [RegisterName; 10] = [
+    RegisterName::R0,
+    RegisterName::R1,
+    RegisterName::R2,
+    RegisterName::R3,
+    RegisterName::R4,
+    RegisterName::R5,
+    RegisterName::R6,
+    RegisterName::R7,
+    RegisterName::Pc,
+    RegisterName::Psr,
+];
+
+//instructions to run between checks for an interrupt from the client
+const CONTINUE_CHUNK: u64 = 10_000;
+
+/// Serves the GDB remote serial protocol on `stream` until the client
+/// detaches, kills the session or disconnects.
+///
+/// Registers are R0-R7, PC and PSR, 16 bits each, sent little endian.
+/// Addresses in packets are LC-3 word addresses; lengths are in bytes, two
+/// per word, with each word sent little endian. Memory is read and written
+/// directly, so device registers have no side effects.
+pub fn serve(machine: &mut Machine, stream: TcpStream) -> io::Result<()> {
+    //packets are small and each waits on a reply
+    stream.set_nodelay(true)?;
+    Session {
+        machine,
+        stream,
+        acknowledge: true,
+    }
+    .run()
+}
+
+struct Session<'a> {
+    machine: &'a mut Machine,
+    stream: TcpStream,
+    //cleared by QStartNoAckMode
+    acknowledge: bool,
+}
+
+enum Reply {
+    Packet(String),
+    /// Reply, then end the session.
+    Close(String),
+    /// End the session without replying.
+    Kill,
+    /// Reply OK, then stop acknowledging packets.
+    StartNoAckMode,
+}
+
+impl<'a> Session<'a> {
+    fn run(&mut self) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            match self.handle(&packet) {
+                Reply::Packet(reply) => self.write_packet(&reply)?,
+                Reply::Close(reply) => {
+                    self.write_packet(&reply)?;
+                    break;
+                }
+                Reply::Kill => break,
+                Reply::StartNoAckMode => {
+                    self.write_packet("OK")?;
+                    self.acknowledge = false;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    /// Reads the next `$data#checksum` packet, acknowledging it. Returns `None`
+    /// when the client disconnects.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            //skip acknowledgements and interrupts outside packets
+            match self.read_byte()? {
+                Some(b'$') => {}
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                    None => return Ok(None),
+                }
+            }
+            let mut checksum = [0; 2];
+            self.stream.read_exact(&mut checksum)?;
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|text| u8::from_str_radix(text, 16).ok());
+            if expected == Some(checksum_of(&data)) {
+                if self.acknowledge {
+                    self.stream.write_all(b"+")?;
+                }
+                //packets are ASCII, and handlers slice them by byte offset
+                if !data.is_ascii() {
+                    self.write_packet("E01")?;
+                    continue;
+                }
+                return Ok(Some(data.into_iter().map(char::from).collect()));
+            }
+            if self.acknowledge {
+                self.stream.write_all(b"-")?;
+            }
+        }
+    }
+
+    fn write_packet(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        loop {
+            self.stream.write_all(packet.as_bytes())?;
+            if !self.acknowledge {
+                return Ok(());
+            }
+            match self.read_byte()? {
+                Some(b'-') => continue,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn handle(&mut self, packet: &str) -> Reply {
+        let (command, arguments) = packet.split_at(packet.len().min(1));
+        let reply = match command {
+            "?" if !self.machine.is_running() => self.stop_reply(StopReason::Halted),
+            "?" => self.stop_reply(StopReason::Breakpoint(self.machine.registers().get_pc())),
+            "g" => REGISTERS
+                .iter()
+                .map(|&register| hex_word(self.machine.registers().get_by_name(register)))
+                .collect(),
+            "G" => self.write_registers(arguments),
+            "p" => match parse_hex(arguments).and_then(|number| REGISTERS.get(number as usize)) {
+                Some(&register) => hex_word(self.machine.registers().get_by_name(register)),
+                None => error(),
+            },
+            "P" => self.write_register(arguments),
+            "m" => self.read_memory(arguments),
+            "M" => self.write_memory(arguments),
+            "s" => {
+                self.resume_at(arguments);
+                let stop = self.step();
+                self.stop_reply(stop)
+            }
+            "c" => {
+                self.resume_at(arguments);
+                let stop = self.resume();
+                self.stop_reply(stop)
+            }
+            "b" if arguments == "s" => {
+                if self.machine.step_back() {
+                    "S05".to_string()
+                } else {
+                    self.stop_reply(StopReason::HistoryStart)
+                }
+            }
+            "b" if arguments == "c" => {
+                let stop = self.machine.run_backward();
+                self.stop_reply(stop)
+            }
+            "Z" => self.set_breakpoint(arguments, true),
+            "z" => self.set_breakpoint(arguments, false),
+            "H" => "OK".to_string(),
+            "q" if arguments == "Attached" => "1".to_string(),
+            "q" if arguments.starts_with("Supported") => {
+                "PacketSize=1000;QStartNoAckMode+;ReverseStep+;ReverseContinue+".to_string()
+            }
+            "Q" if arguments == "StartNoAckMode" => return Reply::StartNoAckMode,
+            "D" => return Reply::Close("OK".to_string()),
+            "k" => return Reply::Kill,
+            _ => String::new(),
+        };
+        Reply::Packet(reply)
+    }
+
+    fn write_registers(&mut self, arguments: &str) -> String {
+        if arguments.len() != REGISTERS.len() * 4 {
+            return error();
+        }
+        let mut values = Vec::new();
+        for index in 0..REGISTERS.len() {
+            match parse_word(&arguments[index * 4..index * 4 + 4]) {
+                Some(value) => values.push(value),
+                None => return error(),
+            }
+        }
+        for (&register, value) in REGISTERS.iter().zip(values) {
+            self.machine.registers_mut().update_by_name(register, value);
+        }
+        "OK".to_string()
+    }
+
+    fn write_register(&mut self, arguments: &str) -> String {
+        let register = arguments.split_once('=').and_then(|(number, value)| {
+            let register = REGISTERS.get(parse_hex(number)? as usize)?;
+            Some((*register, parse_word(value)?))
+        });
+        match register {
+            Some((register, value)) => {
+                self.machine.registers_mut().update_by_name(register, value);
+                "OK".to_string()
+            }
+            None => error(),
+        }
+    }
+
+    fn read_memory(&self, arguments: &str) -> String {
+        let words = match words(arguments) {
+            Some(words) => words,
+            None => return error(),
+        };
+        words
+            .map(|address| hex_word(self.machine.memory()[address as usize]))
+            .collect()
+    }
+
+    fn write_memory(&mut self, arguments: &str) -> String {
+        let (range, data) = match arguments.split_once(':') {
+            Some((range, data)) => (range, data),
+            None => return error(),
+        };
+        let words = match words(range) {
+            Some(words) => words,
+            None => return error(),
+        };
+        if data.len() != words.clone().count() * 4 {
+            return error();
+        }
+        for (index, address) in words.enumerate() {
+            let value = match parse_word(&data[index * 4..index * 4 + 4]) {
+                Some(value) => value,
+                None => return error(),
+            };
+            self.machine.memory_mut()[address as usize] = value;
+        }
+        "OK".to_string()
+    }
+
+    /// `Z`/`z` packets: types 0 and 1 are breakpoints, 2, 3 and 4 write, read
+    /// and access watchpoints.
+    fn set_breakpoint(&mut self, arguments: &str, insert: bool) -> String {
+        let mut fields = arguments.split(',');
+        let kind = fields.next();
+        let address = fields.next().and_then(parse_hex);
+        let length = fields.next().and_then(parse_hex);
+        let (address, length) = match (address, length) {
+            (Some(address), Some(length)) => (address as u16, length),
+            _ => return error(),
+        };
+        let watch = match kind {
+            Some("0") | Some("1") => {
+                if insert {
+                    self.machine.add_breakpoint(address);
+                } else {
+                    self.machine.remove_breakpoint(address);
+                }
+                return "OK".to_string();
+            }
+            Some("2") => WatchKind::Write,
+            Some("3") => WatchKind::Read,
+            Some("4") => WatchKind::Access,
+            _ => return String::new(),
+        };
+        let range = match word_range(address, length) {
+            Some(range) => range,
+            None => return error(),
+        };
+        if insert {
+            self.machine.add_watchpoint(range, watch);
+        } else {
+            self.machine.remove_watchpoint(&range);
+        }
+        "OK".to_string()
+    }
+
+    //`s` and `c` may give an address to resume at
+    fn resume_at(&mut self, arguments: &str) {
+        if let Some(address) = parse_hex(arguments) {
+            self.machine.registers_mut().set_pc(address as u16);
+        }
+    }
+
+    fn step(&mut self) -> StopReason {
+        if !self.machine.is_running() {
+            return StopReason::Halted;
+        }
+        match self.machine.step() {
+            Ok(()) => self
+                .machine
+                .take_watch_hit()
+                .unwrap_or(StopReason::StepLimit),
+            Err(error) => StopReason::Fault(error),
+        }
+    }
+
+    /// Runs until the machine stops or the client sends an interrupt (^C),
+    /// which is reported as a step limit.
+    fn resume(&mut self) -> StopReason {
+        loop {
+            match self.machine.run_for(CONTINUE_CHUNK) {
+                StopReason::StepLimit if !self.interrupted() => {}
+                stop => return stop,
+            }
+        }
+    }
+
+    fn interrupted(&mut self) -> bool {
+        let mut byte = [0];
+        if self.stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let interrupted = match self.stream.read(&mut byte) {
+            Ok(1) => byte[0] == 0x03,
+            //a disconnected client can't resume the machine, so stop it
+            Ok(_) => true,
+            Err(ref error) if error.kind() == ErrorKind::WouldBlock => false,
+            Err(_) => true,
+        };
+        self.stream.set_nonblocking(false).ok();
+        interrupted
+    }
+
+    fn stop_reply(&self, stop: StopReason) -> String {
+        match stop {
+            StopReason::Halted => "W00".to_string(),
+            //the machine stopped without a fault: a step, a breakpoint or ^C
+            StopReason::StepLimit => "S05".to_string(),
+            StopReason::Breakpoint(_) => "S05".to_string(),
+            StopReason::Watchpoint { address, .. } => format!("T05watch:{:x};", address),
+            StopReason::HistoryStart => "T05replaylog:begin;".to_string(),
+            StopReason::Fault(MachineError::ReservedOpcode { .. }) => "S04".to_string(),
+            StopReason::Fault(_) => "S0b".to_string(),
+        }
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum: u8, &byte| sum.wrapping_add(byte))
+}
+
+fn error() -> String {
+    "E01".to_string()
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
+
+/// A little endian word, as four hex digits.
+fn hex_word(word: u16) -> String {
+    let mut text = String::new();
+    for byte in word.to_le_bytes().iter() {
+        write!(text, "{:02x}", byte).unwrap();
+    }
+    text
+}
+
+fn parse_word(text: &str) -> Option<u16> {
+    if text.len() != 4 {
+        return None;
+    }
+    u16::from_str_radix(text, 16).ok().map(u16::swap_bytes)
+}
+
+/// The words covered by `length` bytes at word `address`.
+fn word_range(address: u16, length: u32) -> Option<RangeInclusive<u16>> {
+    let words = length.div_ceil(2);
+    if words == 0 {
+        return None;
+    }
+    let end = address as u32 + words - 1;
+    if end > u16::MAX as u32 {
+        return None;
+    }
+    Some(address..=end as u16)
+}
+
+/// The word addresses named by an `ADDRESS,LENGTH` argument.
+fn words(arguments: &str) -> Option<RangeInclusive<u16>> {
+    let (address, length) = arguments.split_once(',')?;
+    word_range(parse_hex(address)? as u16, parse_hex(length)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::console::BufferConsole;
+    use std::net::TcpListener;
+    use std::thread;
+
+    struct Client(TcpStream);
+
+    impl Client {
+        fn byte(&mut self) -> u8 {
+            let mut byte = [0];
+            self.0.read_exact(&mut byte).unwrap();
+            byte[0]
+        }
+
+        fn send(&mut self, packet: &str) -> String {
+            self.send_bytes(packet.as_bytes())
+        }
+
+        fn send_bytes(&mut self, packet: &[u8]) -> String {
+            self.0.write_all(b"$").unwrap();
+            self.0.write_all(packet).unwrap();
+            write!(self.0, "#{:02x}", checksum_of(packet)).unwrap();
+            assert_eq!(self.byte(), b'+');
+            assert_eq!(self.byte(), b'$');
+            let mut reply = Vec::new();
+            loop {
+                match self.byte() {
+                    b'#' => break,
+                    byte => reply.push(byte),
+                }
+            }
+            let checksum = [self.byte(), self.byte()];
+            let checksum = u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16);
+            assert_eq!(checksum, Ok(checksum_of(&reply)));
+            self.0.write_all(b"+").unwrap();
+            String::from_utf8(reply).unwrap()
+        }
+    }
+
+    #[test]
+    fn it_serves_a_socket_client() {
+        let program = crate::assembler::assemble(
+            ".ORIG x3000
+                  AND R1, R1, #0
+                  ADD R1, R1, #1
+                  ADD R1, R1, #1
+                  ST R1, COUNT
+                  HALT
+            COUNT .FILL #0
+            .END",
+        )
+        .unwrap();
+        let mut machine = Machine::empty()
+            .with_console(Box::new(BufferConsole::new(b"")))
+            .start();
+        machine.load_object(&program.object);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut client = Client(TcpStream::connect(address).unwrap());
+            client.0.set_nodelay(true).unwrap();
+            let mut replies = Vec::new();
+            for packet in [
+                "qSupported:swbreak+",
+                "?",
+                "g",
+                "m3000,4",
+                "Z0,3002,2",
+                "c",
+                "p8",
+                "z0,3002,2",
+                "s",
+                "P1=0900",
+                "M4000,2:3412",
+                "m4000,2",
+                "Mffff,2:cdab",
+                "mffff,2",
+                "Z2,3005,2",
+                "c",
+                "p1",
+                "Géééééééééééééééééééé",
+                "M3000,2:éé",
+                "é",
+                "c",
+                "?",
+            ]
+            .iter()
+            {
+                replies.push(client.send(packet));
+            }
+            //not even UTF-8
+            replies.push(client.send_bytes(b"\xff"));
+            write!(client.0, "$k#6b").unwrap();
+            replies
+        });
+        let (stream, _) = listener.accept().unwrap();
+        serve(&mut machine, stream).unwrap();
+
+        let replies = client.join().unwrap();
+        assert_eq!(
+            replies,
+            [
+                "PacketSize=1000;QStartNoAckMode+;ReverseStep+;ReverseContinue+",
+                "S05",
+                "0000000000000000000000000000000000300200",
+                "60526112",
+                "OK",
+                "S05",
+                "0230",
+                "OK",
+                "S05",
+                "OK",
+                "OK",
+                "3412",
+                "OK",
+                "cdab",
+                "OK",
+                "T05watch:3005;",
+                "0900",
+                "E01",
+                "E01",
+                "E01",
+                "W00",
+                "W00",
+                "E01",
+            ]
+        );
+        assert_eq!(machine.memory()[0x3005], 9);
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::VecDeque;
+
+use crate::registers::Registers;
+
+/// What one executed instruction changed: the registers before it ran and the
+/// previous value of every word it wrote, in order.
+#[derive(Clone, Debug)]
+pub struct Change {
+    pub registers: Registers,
+    pub writes: Vec<(u16, u16)>,
+}
+
+impl Change {
+    pub fn new(registers: Registers) -> Change {
+        Change {
+            registers,
+            writes: Vec::new(),
+        }
+    }
+}
+
+/// An undo log of executed instructions, most recent last.
+///
+/// Changes are kept in segments that start at a checkpoint every
+/// [`History::CHECKPOINT_INTERVAL`] instructions. Once more than `limit`
+/// changes are held, the oldest segment is dropped, so memory use stays
+/// bounded and the oldest state that can be reached is always a checkpoint.
+/// A limit smaller than one segment trims the oldest changes one at a time
+/// instead, so the log never empties itself.
+#[derive(Clone, Debug)]
+pub struct History {
+    segments: VecDeque<Vec<Change>>,
+    limit: usize,
+    len: usize,
+}
+
+impl History {
+    pub const CHECKPOINT_INTERVAL: usize = 4096;
+    /// A limit that fits comfortably in memory for most programs.
+    pub const DEFAULT_LIMIT: usize = 1 << 20;
+
+    pub fn new(limit: usize) -> History {
+        History {
+            segments: VecDeque::new(),
+            limit,
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, change: Change) {
+        match self.segments.back_mut() {
+            Some(segment) if segment.len() < History::CHECKPOINT_INTERVAL => segment.push(change),
+            _ => {
+                let mut segment = Vec::with_capacity(History::CHECKPOINT_INTERVAL);
+                segment.push(change);
+                self.segments.push_back(segment);
+            }
+        }
+        self.len += 1;
+        while self.len > self.limit {
+            if self.segments.len() > 1 {
+                let oldest = self.segments.pop_front().unwrap();
+                self.len -= oldest.len();
+            } else {
+                //only reached while the limit is below one segment, so this is cheap
+                match self.segments.front_mut() {
+                    Some(segment) => {
+                        segment.remove(0);
+                        self.len -= 1;
+                    }
+                    None => break,
+                }
+            }
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<Change> {
+        let segment = self.segments.back_mut()?;
+        let change = segment.pop();
+        if segment.is_empty() {
+            self.segments.pop_back();
+        }
+        if change.is_some() {
+            self.len -= 1;
+        }
+        change
+    }
+
+    pub fn last(&self) -> Option<&Change> {
+        self.segments.back().and_then(|segment| segment.last())
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// The number of instructions that can be undone.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(pc: u16) -> Change {
+        let mut registers = Registers::new();
+        registers.set_pc(pc);
+        Change::new(registers)
+    }
+
+    #[test]
+    fn it_drops_the_oldest_segment_past_the_limit() {
+        let mut history = History::new(History::CHECKPOINT_INTERVAL + 1);
+        for pc in 0..=History::CHECKPOINT_INTERVAL as u16 {
+            history.push(change(pc));
+        }
+        assert_eq!(history.len(), History::CHECKPOINT_INTERVAL + 1);
+
+        history.push(change(0xFFFF));
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.pop().unwrap().registers.get_pc(), 0xFFFF);
+        assert_eq!(
+            history.pop().unwrap().registers.get_pc(),
+            History::CHECKPOINT_INTERVAL as u16
+        );
+        assert!(history.pop().is_none());
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn it_keeps_the_most_recent_changes_under_a_small_limit() {
+        let mut history = History::new(10);
+        for pc in 0..11 {
+            history.push(change(pc));
+        }
+        assert_eq!(history.len(), 10);
+        assert_eq!(history.last().unwrap().registers.get_pc(), 10);
+        let mut oldest = None;
+        while let Some(change) = history.pop() {
+            oldest = Some(change.registers.get_pc());
+        }
+        assert_eq!(oldest, Some(1));
+    }
+}
@@ -0,0 +1,336 @@
+use crate::opcodes::Opcodes;
+use crate::registers::RegisterName;
+use crate::util::sign_extend;
+
+/// The second source of ADD and AND.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Register(RegisterName),
+    /// imm5, sign extended.
+    Immediate(i16),
+}
+
+/// A decoded LC-3 instruction. Offsets and immediates are stored sign extended;
+/// `encode` truncates them to the width of their field. Register fields must
+/// be R0-R7.
+///
+/// Decoding is lossless: a word whose unused bits are not as the ISA specifies
+/// decodes to [`Instruction::Nonstandard`], so `Instruction::decode(word).encode()`
+/// is `word` for every 16-bit word. The CPU ignores those bits, as
+/// [`Instruction::decode_fields`] does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    Br {
+        nzp: u8,
+        offset9: i16,
+    },
+    Add {
+        dr: RegisterName,
+        sr1: RegisterName,
+        operand: Operand,
+    },
+    Ld {
+        dr: RegisterName,
+        offset9: i16,
+    },
+    St {
+        sr: RegisterName,
+        offset9: i16,
+    },
+    Jsr {
+        offset11: i16,
+    },
+    Jsrr {
+        base: RegisterName,
+    },
+    And {
+        dr: RegisterName,
+        sr1: RegisterName,
+        operand: Operand,
+    },
+    Ldr {
+        dr: RegisterName,
+        base: RegisterName,
+        offset6: i16,
+    },
+    Str {
+        sr: RegisterName,
+        base: RegisterName,
+        offset6: i16,
+    },
+    Rti,
+    Not {
+        dr: RegisterName,
+        sr: RegisterName,
+    },
+    Ldi {
+        dr: RegisterName,
+        offset9: i16,
+    },
+    Sti {
+        sr: RegisterName,
+        offset9: i16,
+    },
+    /// JMP; `RET` is `Jmp { base: R7 }`.
+    Jmp {
+        base: RegisterName,
+    },
+    /// Any word with opcode 13.
+    Reserved(u16),
+    Lea {
+        dr: RegisterName,
+        offset9: i16,
+    },
+    Trap {
+        trapvect8: u8,
+    },
+    /// A word whose unused bits are not as the ISA specifies. It still executes
+    /// as the instruction its opcode and fields name.
+    Nonstandard(u16),
+}
+
+impl Instruction {
+    pub fn decode(word: u16) -> Instruction {
+        let instruction = Instruction::decode_fields(word);
+        if instruction.encode() == word {
+            instruction
+        } else {
+            Instruction::Nonstandard(word)
+        }
+    }
+
+    /// Decodes by opcode and fields alone, ignoring bits the ISA leaves unused,
+    /// as the CPU does. Never returns [`Instruction::Nonstandard`].
+    pub fn decode_fields(word: u16) -> Instruction {
+        let dr = RegisterName::from_field(word >> 9);
+        let sr1 = RegisterName::from_field(word >> 6);
+        let offset = |bits: usize| sign_extend(word & ((1 << bits) - 1), bits) as i16;
+        let operand = || {
+            if (word >> 5) & 1 == 1 {
+                Operand::Immediate(offset(5))
+            } else {
+                Operand::Register(RegisterName::from_field(word))
+            }
+        };
+        let maybe_opcode: Option<Opcodes> = num::FromPrimitive::from_u16(word >> 12);
+        match maybe_opcode {
+            None => unreachable!("every four bit opcode is named"),
+            Some(Opcodes::Branch) => Instruction::Br {
+                nzp: ((word >> 9) & 0b111) as u8,
+                offset9: offset(9),
+            },
+            Some(Opcodes::Add) => Instruction::Add {
+                dr,
+                sr1,
+                operand: operand(),
+            },
+            Some(Opcodes::Load) => Instruction::Ld {
+                dr,
+                offset9: offset(9),
+            },
+            Some(Opcodes::Store) => Instruction::St {
+                sr: dr,
+                offset9: offset(9),
+            },
+            Some(Opcodes::JumpRegister) if (word >> 11) & 1 == 1 => Instruction::Jsr {
+                offset11: offset(11),
+            },
+            Some(Opcodes::JumpRegister) => Instruction::Jsrr { base: sr1 },
+            Some(Opcodes::And) => Instruction::And {
+                dr,
+                sr1,
+                operand: operand(),
+            },
+            Some(Opcodes::LoadRegister) => Instruction::Ldr {
+                dr,
+                base: sr1,
+                offset6: offset(6),
+            },
+            Some(Opcodes::StoreRegister) => Instruction::Str {
+                sr: dr,
+                base: sr1,
+                offset6: offset(6),
+            },
+            Some(Opcodes::Rti) => Instruction::Rti,
+            Some(Opcodes::Not) => Instruction::Not { dr, sr: sr1 },
+            Some(Opcodes::LoadIndirect) => Instruction::Ldi {
+                dr,
+                offset9: offset(9),
+            },
+            Some(Opcodes::StoreIndirect) => Instruction::Sti {
+                sr: dr,
+                offset9: offset(9),
+            },
+            Some(Opcodes::Jump) => Instruction::Jmp { base: sr1 },
+            Some(Opcodes::Reserved) => Instruction::Reserved(word),
+            Some(Opcodes::LoadEffectiveAddress) => Instruction::Lea {
+                dr,
+                offset9: offset(9),
+            },
+            Some(Opcodes::ExecuteTrap) => Instruction::Trap {
+                trapvect8: word as u8,
+            },
+        }
+    }
+
+    pub fn encode(&self) -> u16 {
+        let register = |register: RegisterName| {
+            //PC, COND and PSR have no encoding and would alias R0-R2
+            debug_assert!(
+                (register as u16) < 8,
+                "{:?} is not a general purpose register",
+                register
+            );
+            (register as u16) & 0b111
+        };
+        let field = |value: i16, bits: u16| (value as u16) & ((1 << bits) - 1);
+        let opcode = |opcode: Opcodes| (opcode as u16) << 12;
+        let operand = |operand: Operand| match operand {
+            Operand::Register(sr2) => register(sr2),
+            Operand::Immediate(imm5) => 1 << 5 | field(imm5, 5),
+        };
+        match *self {
+            Instruction::Br { nzp, offset9 } => {
+                opcode(Opcodes::Branch) | (nzp as u16 & 0b111) << 9 | field(offset9, 9)
+            }
+            Instruction::Add {
+                dr,
+                sr1,
+                operand: source,
+            } => opcode(Opcodes::Add) | register(dr) << 9 | register(sr1) << 6 | operand(source),
+            Instruction::Ld { dr, offset9 } => {
+                opcode(Opcodes::Load) | register(dr) << 9 | field(offset9, 9)
+            }
+            Instruction::St { sr, offset9 } => {
+                opcode(Opcodes::Store) | register(sr) << 9 | field(offset9, 9)
+            }
+            Instruction::Jsr { offset11 } => {
+                opcode(Opcodes::JumpRegister) | 1 << 11 | field(offset11, 11)
+            }
+            Instruction::Jsrr { base } => opcode(Opcodes::JumpRegister) | register(base) << 6,
+            Instruction::And {
+                dr,
+                sr1,
+                operand: source,
+            } => opcode(Opcodes::And) | register(dr) << 9 | register(sr1) << 6 | operand(source),
+            Instruction::Ldr { dr, base, offset6 } => {
+                opcode(Opcodes::LoadRegister)
+                    | register(dr) << 9
+                    | register(base) << 6
+                    | field(offset6, 6)
+            }
+            Instruction::Str { sr, base, offset6 } => {
+                opcode(Opcodes::StoreRegister)
+                    | register(sr) << 9
+                    | register(base) << 6
+                    | field(offset6, 6)
+            }
+            Instruction::Rti => opcode(Opcodes::Rti),
+            Instruction::Not { dr, sr } => {
+                opcode(Opcodes::Not) | register(dr) << 9 | register(sr) << 6 | 0b111111
+            }
+            Instruction::Ldi { dr, offset9 } => {
+                opcode(Opcodes::LoadIndirect) | register(dr) << 9 | field(offset9, 9)
+            }
+            Instruction::Sti { sr, offset9 } => {
+                opcode(Opcodes::StoreIndirect) | register(sr) << 9 | field(offset9, 9)
+            }
+            Instruction::Jmp { base } => opcode(Opcodes::Jump) | register(base) << 6,
+            Instruction::Lea { dr, offset9 } => {
+                opcode(Opcodes::LoadEffectiveAddress) | register(dr) << 9 | field(offset9, 9)
+            }
+            Instruction::Trap { trapvect8 } => opcode(Opcodes::ExecuteTrap) | trapvect8 as u16,
+            Instruction::Reserved(word) | Instruction::Nonstandard(word) => word,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_round_trips_every_word() {
+        for word in 0..=u16::MAX {
+            assert_eq!(Instruction::decode(word).encode(), word, "x{:04X}", word);
+        }
+    }
+
+    #[test]
+    fn it_decodes_typed_fields() {
+        assert_eq!(
+            Instruction::decode(0x1642),
+            Instruction::Add {
+                dr: RegisterName::R3,
+                sr1: RegisterName::R1,
+                operand: Operand::Register(RegisterName::R2)
+            }
+        );
+        assert_eq!(
+            Instruction::decode(0x103F),
+            Instruction::Add {
+                dr: RegisterName::R0,
+                sr1: RegisterName::R0,
+                operand: Operand::Immediate(-1)
+            }
+        );
+        assert_eq!(
+            Instruction::decode(0x0FFE),
+            Instruction::Br {
+                nzp: 0b111,
+                offset9: -2
+            }
+        );
+        assert_eq!(
+            Instruction::decode(0x71A0),
+            Instruction::Str {
+                sr: RegisterName::R0,
+                base: RegisterName::R6,
+                offset6: -32
+            }
+        );
+        assert_eq!(
+            Instruction::decode(0x4C00),
+            Instruction::Jsr { offset11: -1024 }
+        );
+        assert_eq!(
+            Instruction::decode(0xC1C0),
+            Instruction::Jmp {
+                base: RegisterName::R7
+            }
+        );
+        assert_eq!(
+            Instruction::decode(0xF025),
+            Instruction::Trap { trapvect8: 0x25 }
+        );
+        assert_eq!(Instruction::decode(0xD123), Instruction::Reserved(0xD123));
+    }
+
+    #[test]
+    fn it_keeps_nonstandard_encodings_whole() {
+        for &word in [0x1648, 0x92BE, 0xC1C1, 0x4600, 0x8001, 0xF125].iter() {
+            assert_eq!(Instruction::decode(word), Instruction::Nonstandard(word));
+            assert_ne!(
+                Instruction::decode_fields(word),
+                Instruction::Nonstandard(word)
+            );
+        }
+        assert_eq!(
+            Instruction::decode_fields(0x92BE),
+            Instruction::Not {
+                dr: RegisterName::R1,
+                sr: RegisterName::R2
+            }
+        );
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Pc is not a general purpose register")]
+    fn it_refuses_to_encode_special_registers() {
+        Instruction::Jmp {
+            base: RegisterName::Pc,
+        }
+        .encode();
+    }
+}
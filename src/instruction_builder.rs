@@ -1,88 +1,136 @@
 #[cfg(test)]
 pub mod instructions {
 
+    use crate::instruction::{Instruction, Operand};
     use crate::registers::{ConditionFlag, RegisterName};
+    use crate::util::sign_extend;
+
+    //offsets are given as raw field bits, so only the low `bits` bits are used
+    fn offset(value: u16, bits: usize) -> i16 {
+        sign_extend(value & ((1 << bits) - 1), bits) as i16
+    }
+
     pub fn increment(register: RegisterName) -> u16 {
-        let r_value = register as u16;
-        (1 << 12) | ((r_value) << 9) | ((r_value) << 6) | 1 << 5 | 1
+        add_immediate(register, 1)
     }
 
     pub fn decrement(register: RegisterName) -> u16 {
-        let r_value = register as u16;
-        (1 << 12) | ((r_value) << 9) | ((r_value) << 6) | 1 << 5 | 0b11111
+        add_immediate(register, 0b11111)
     }
 
     pub fn add_immediate(register: RegisterName, value: u16) -> u16 {
-        //will only take 4 bytes of u16 to add
-        let r_value = register as u16;
-        (1 << 12) | ((r_value) << 9) | ((r_value) << 6) | 1 << 5 | (value & 0b11111)
+        Instruction::Add {
+            dr: register,
+            sr1: register,
+            operand: Operand::Immediate(offset(value, 5)),
+        }
+        .encode()
     }
 
     pub fn and_immediate(register: RegisterName, value: u16) -> u16 {
-        //will only take 4 bytes of u16 to add
-        let r_value = register as u16;
-        (0b101 << 12) | ((r_value) << 9) | ((r_value) << 6) | 1 << 5 | (value & 0b11111)
+        Instruction::And {
+            dr: register,
+            sr1: register,
+            operand: Operand::Immediate(offset(value, 5)),
+        }
+        .encode()
     }
 
     pub fn and_register(source1: RegisterName, source2: RegisterName, dest: RegisterName) -> u16 {
-        //will only take 4 bytes of u16 to add
-        (0b101 << 12)
-            | ((dest as u16) << 9)
-            | ((source1 as u16) << 6)
-            | 0 << 5
-            | (source2 as u16 & 0b111)
+        Instruction::And {
+            dr: dest,
+            sr1: source1,
+            operand: Operand::Register(source2),
+        }
+        .encode()
     }
 
     pub fn store(register: RegisterName, pc_offset: u16) -> u16 {
-        //will only take 4 bytes of u16 to add
-        let source = register as u16;
-        (0b11 << 12) | ((source) << 9) | (pc_offset & 0b111111111)
+        Instruction::St {
+            sr: register,
+            offset9: offset(pc_offset, 9),
+        }
+        .encode()
     }
 
     pub fn load(register: RegisterName, pc_offset: u16) -> u16 {
-        //will only take 4 bytes of u16 to add
-        let destination = register as u16;
-        (0b10 << 12) | ((destination) << 9) | (pc_offset & 0b111111111)
+        Instruction::Ld {
+            dr: register,
+            offset9: offset(pc_offset, 9),
+        }
+        .encode()
     }
 
     pub fn load_indirect(register: RegisterName, pc_offset: u16) -> u16 {
-        //will only take 4 bytes of u16 to add
-        let destination = register as u16;
-        (0b1010 << 12) | ((destination) << 9) | (pc_offset & 0b111111111)
+        Instruction::Ldi {
+            dr: register,
+            offset9: offset(pc_offset, 9),
+        }
+        .encode()
     }
 
     pub fn add(source_1: RegisterName, source_2: RegisterName, dest: RegisterName) -> u16 {
-        (1 << 12) | ((dest as u16) << 9) | ((source_1 as u16) << 6) | 0 << 5 | source_2 as u16
+        Instruction::Add {
+            dr: dest,
+            sr1: source_1,
+            operand: Operand::Register(source_2),
+        }
+        .encode()
     }
 
-    pub fn jump_offset(offset: u16) -> u16 {
-        (0b100 << 12) | (1 << 11) | (offset & 0x7FF)
+    pub fn jump_offset(offset_11: u16) -> u16 {
+        Instruction::Jsr {
+            offset11: offset(offset_11, 11),
+        }
+        .encode()
     }
 
     pub fn jump_register(register: RegisterName) -> u16 {
-        (0b100 << 12) | 0 << 11 | (((register as u16) & 0b111) << 6)
+        Instruction::Jsrr { base: register }.encode()
     }
 
     pub fn not(source: RegisterName, destination: RegisterName) -> u16 {
-        (0b1001 << 12) | ((destination as u16) << 9) | ((source as u16) << 6) | 0b111111
+        Instruction::Not {
+            dr: destination,
+            sr: source,
+        }
+        .encode()
     }
 
     pub fn branch(condition_flag: ConditionFlag, pc_offset: u16) -> u16 {
-        (condition_flag as u16) << 9 | (pc_offset & 0x1FF)
+        Instruction::Br {
+            nzp: condition_flag as u8,
+            offset9: offset(pc_offset, 9),
+        }
+        .encode()
     }
 
     pub fn load_register(
         base_register: RegisterName,
-        offset: u16,
+        offset_6: u16,
         destination: RegisterName,
     ) -> u16 {
-        (0b110 << 12)
-            | (destination as u16) << 9
-            | (base_register as u16) << 6
-            | (offset & 0b111111)
+        Instruction::Ldr {
+            dr: destination,
+            base: base_register,
+            offset6: offset(offset_6, 6),
+        }
+        .encode()
+    }
+
+    pub fn store_register(base_register: RegisterName, offset_6: u16, source: RegisterName) -> u16 {
+        Instruction::Str {
+            sr: source,
+            base: base_register,
+            offset6: offset(offset_6, 6),
+        }
+        .encode()
     }
 
-    pub fn store_register(base_register: RegisterName, offset: u16, source: RegisterName) -> u16 {
-        (0b110 << 12) | (source as u16) << 9 | (base_register as u16) << 6 | (offset & 0b111111)
+    pub fn trap(trap_vector: u16) -> u16 {
+        Instruction::Trap {
+            trapvect8: trap_vector as u8,
+        }
+        .encode()
     }
 }
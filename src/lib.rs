@@ -0,0 +1,26 @@
+extern crate num;
+#[macro_use]
+extern crate num_derive;
+pub mod assembler;
+pub mod condition;
+pub mod console;
+pub mod debugger;
+pub mod devices;
+pub mod diagnostics;
+pub mod disassembler;
+pub mod error;
+pub mod gdb;
+pub mod history;
+pub mod instruction;
+mod instruction_builder;
+pub mod loader;
+pub mod machine;
+pub mod opcodes;
+pub mod os;
+pub mod registers;
+pub mod snapshot;
+pub mod symbols;
+pub mod trace;
+pub mod tracediff;
+pub mod traps;
+pub mod util;
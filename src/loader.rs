@@ -0,0 +1,146 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::machine::MEMORY_SIZE;
+
+/// A program image in the standard LC-3 object format: a sequence of
+/// big-endian words where the first word is the origin and the rest are
+/// placed in memory consecutively starting at that origin.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjectFile {
+    pub origin: u16,
+    pub words: Vec<u16>,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// The file is too short to contain an origin word.
+    Truncated,
+    /// The file ends in the middle of a word.
+    OddLength(usize),
+    /// The image would extend past the last addressable word of memory.
+    OutOfBounds {
+        origin: u16,
+        length: usize,
+    },
+}
+
+impl ObjectFile {
+    pub fn from_bytes(bytes: &[u8]) -> Result<ObjectFile, LoadError> {
+        if bytes.len() < 2 {
+            return Err(LoadError::Truncated);
+        }
+        if bytes.len() % 2 != 0 {
+            return Err(LoadError::OddLength(bytes.len()));
+        }
+        let mut words = bytes
+            .chunks(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]));
+        let origin = words.next().unwrap();
+        let words: Vec<u16> = words.collect();
+        if origin as usize + words.len() > MEMORY_SIZE {
+            return Err(LoadError::OutOfBounds {
+                origin,
+                length: words.len(),
+            });
+        }
+        Ok(ObjectFile { origin, words })
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<ObjectFile, LoadError> {
+        let bytes = fs::read(path)?;
+        ObjectFile::from_bytes(&bytes)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity((self.words.len() + 1) * 2);
+        bytes.extend_from_slice(&self.origin.to_be_bytes());
+        for word in &self.words {
+            bytes.extend_from_slice(&word.to_be_bytes());
+        }
+        bytes
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(error) => write!(f, "{}", error),
+            LoadError::Truncated => write!(f, "object file has no origin word"),
+            LoadError::OddLength(length) => {
+                write!(f, "object file has an odd number of bytes ({})", length)
+            }
+            LoadError::OutOfBounds { origin, length } => write!(
+                f,
+                "image of {} words at x{:04X} runs past the end of memory",
+                length, origin
+            ),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(error: io::Error) -> LoadError {
+        LoadError::Io(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_reads_big_endian_words_after_the_origin() {
+        let object = ObjectFile::from_bytes(&[0x30, 0x00, 0x12, 0x34, 0xF0, 0x25]).unwrap();
+        assert_eq!(object.origin, 0x3000);
+        assert_eq!(object.words, vec![0x1234, 0xF025]);
+        assert_eq!(object.to_bytes(), vec![0x30, 0x00, 0x12, 0x34, 0xF0, 0x25]);
+    }
+
+    #[test]
+    fn it_rejects_truncated_files() {
+        assert!(matches!(
+            ObjectFile::from_bytes(&[]),
+            Err(LoadError::Truncated)
+        ));
+        assert!(matches!(
+            ObjectFile::from_bytes(&[0x30]),
+            Err(LoadError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn it_rejects_odd_byte_counts() {
+        assert!(matches!(
+            ObjectFile::from_bytes(&[0x30, 0x00, 0x12]),
+            Err(LoadError::OddLength(3))
+        ));
+    }
+
+    #[test]
+    fn it_rejects_images_past_the_end_of_memory() {
+        let fits = [0xFF, 0xFE, 0, 1, 0, 2];
+        assert!(ObjectFile::from_bytes(&fits).is_ok());
+        let overflows = [0xFF, 0xFF, 0, 1, 0, 2];
+        assert!(matches!(
+            ObjectFile::from_bytes(&overflows),
+            Err(LoadError::OutOfBounds {
+                origin: 0xFFFF,
+                length: 2
+            })
+        ));
+    }
+}
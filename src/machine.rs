@@ -1,237 +1,995 @@
-const MEMORY_SIZE: usize = u16::MAX as usize;
-use util::sign_extend;
+pub const MEMORY_SIZE: usize = 1 << 16;
+/// Keyboard status register; bit 15 is set while a key is waiting in KBDR.
+pub const KBSR: u16 = 0xFE00;
+/// Keyboard data register; reading it consumes the waiting key.
+pub const KBDR: u16 = 0xFE02;
+/// Display status register; bit 15 is set when DDR can accept a character.
+pub const DSR: u16 = 0xFE04;
+/// Display data register; writing it prints the low byte.
+pub const DDR: u16 = 0xFE06;
+/// Machine control register.
+pub const MCR: u16 = 0xFFFE;
+pub const MCR_CLOCK_ENABLE: u16 = 1 << 15;
+/// Set by the bundled OS's exception handlers before they halt.
+pub const MCR_FAULT: u16 = 1;
+/// Interrupt and exception vectors are looked up at `VECTOR_TABLE | vector`.
+pub const VECTOR_TABLE: u16 = 0x0100;
+/// The addresses user mode code may access; the rest is system space and the I/O page.
+pub const USER_SPACE: RangeInclusive<u16> = 0x3000..=0xFDFF;
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use crate::condition::Condition;
+use crate::console::{Console, StdConsole};
+use crate::devices::{BusError, Device, DeviceBus, Display, Keyboard};
+use crate::error::MachineError;
+use crate::history::{Change, History};
+use crate::instruction::{Instruction, Operand};
+use crate::loader::{LoadError, ObjectFile};
+use crate::os;
+use crate::registers::{RegisterName, Registers, INITIAL_SSP};
+use crate::snapshot::{Snapshot, SnapshotError};
+use crate::trace::{TraceRecord, TraceSink};
+use crate::traps::Traps;
+
+/// Why a call to [`Machine::run`] or [`Machine::run_for`] returned.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StopReason {
+    /// The machine is no longer running, e.g. after a HALT.
+    Halted,
+    /// The step budget given to `run_for` was used up.
+    StepLimit,
+    /// The PC reached a breakpoint; the instruction there has not executed yet.
+    Breakpoint(u16),
+    /// An instruction faulted. The PC is left pointing at it.
+    Fault(MachineError),
+    /// Reverse execution undid the oldest instruction in the history.
+    HistoryStart,
+    /// The instruction at `pc` accessed a watched address and has completed.
+    /// For reads, `old` and `new` are both the value read.
+    Watchpoint {
+        address: u16,
+        pc: u16,
+        old: u16,
+        new: u16,
+    },
+}
+
+/// The options of a breakpoint. Execution stops there once the condition, if
+/// any, holds and the breakpoint has been hit more than `ignore_count` times.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Breakpoint {
+    pub condition: Option<Condition>,
+    pub ignore_count: u32,
+    /// How many times the PC has reached the breakpoint with its condition holding.
+    pub hits: u32,
+}
+
+/// The accesses a watchpoint stops on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+impl WatchKind {
+    fn matches(self, write: bool) -> bool {
+        match self {
+            WatchKind::Read => !write,
+            WatchKind::Write => write,
+            WatchKind::Access => true,
+        }
+    }
+}
 
-use crate::opcodes::Opcodes;
-use crate::registers::{RegisterName, Registers};
-use crate::util;
 pub struct Machine {
     memory: [u16; MEMORY_SIZE],
     registers: Registers,
-    running: bool,
+    //the machine control register; bit 15 is the clock enable
+    mcr: u16,
+    console: Box<dyn Console>,
+    breakpoints: HashMap<u16, Breakpoint>,
+    //the breakpoint execution last stopped at, which is not reported again on resume
+    stopped_at: Option<u16>,
+    watchpoints: Vec<(RangeInclusive<u16>, WatchKind)>,
+    //the first watched access made by the current instruction
+    watch_hit: Option<StopReason>,
+    history: Option<History>,
+    //what the current instruction has changed, while recording history or tracing
+    change: Option<Change>,
+    trace: Option<Box<dyn TraceSink>>,
+    //the instruction being executed, for attributing faults
+    fetched_pc: u16,
+    fetched_instruction: u16,
+    devices: DeviceBus,
+    //whether TRAPs to the standard vectors run the routines built into the
+    //simulator rather than the ones in memory
+    host_traps: bool,
 }
 impl Machine {
     pub fn empty() -> Machine {
         Machine {
             memory: [0; MEMORY_SIZE],
             registers: Registers::new(),
-            running: false,
+            mcr: 0,
+            console: Box::new(StdConsole),
+            breakpoints: HashMap::new(),
+            stopped_at: None,
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            history: None,
+            change: None,
+            trace: None,
+            fetched_pc: 0,
+            fetched_instruction: 0,
+            devices: Machine::standard_devices(),
+            host_traps: true,
+        }
+    }
+
+    fn standard_devices() -> DeviceBus {
+        let mut devices = DeviceBus::new();
+        devices
+            .map(KBSR..=KBDR, Box::new(Keyboard::default()))
+            .expect("the keyboard maps into the I/O page");
+        devices
+            .map(DSR..=DDR, Box::new(Display))
+            .expect("the display maps into the I/O page");
+        devices
+    }
+
+    /// Maps `device` over `range` of the I/O page. Accesses there no longer reach memory.
+    pub fn attach_device(
+        &mut self,
+        range: RangeInclusive<u16>,
+        device: Box<dyn Device>,
+    ) -> Result<(), BusError> {
+        self.devices.map(range, device)
+    }
+
+    pub fn with_console(mut self, console: Box<dyn Console>) -> Machine {
+        self.console = console;
+        self
+    }
+
+    /// Loads the bundled operating system into system space. TRAPs then run
+    /// its routines instead of the ones built into the simulator.
+    pub fn with_os(mut self) -> Machine {
+        let image = &os::program().object;
+        let start = image.origin as usize;
+        self.memory[start..start + image.words.len()].copy_from_slice(&image.words);
+        self.host_traps = false;
+        //as the OS boot code would, so interrupts have a stack to use
+        self.registers.update_by_name(RegisterName::R6, INITIAL_SSP);
+        self
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.mcr & MCR_CLOCK_ENABLE != 0
+    }
+
+    /// The machine control register at xFFFE. Clearing bit 15, the clock
+    /// enable, stops the machine at the end of the current instruction;
+    /// setting it lets `run` continue. Bit 0, [`MCR_FAULT`], records that
+    /// the bundled OS halted on an exception.
+    pub fn mcr(&self) -> u16 {
+        self.mcr
+    }
+
+    pub fn set_mcr(&mut self, mcr: u16) {
+        self.mcr = mcr;
+    }
+
+    fn set_clock_enabled(&mut self, enabled: bool) {
+        if enabled {
+            self.write_mcr(self.mcr | MCR_CLOCK_ENABLE);
+        } else {
+            self.write_mcr(self.mcr & !MCR_CLOCK_ENABLE);
         }
     }
 
+    fn write_mcr(&mut self, value: u16) {
+        if let Some(change) = self.change.as_mut() {
+            change.writes.push((MCR, self.mcr));
+        }
+        self.mcr = value;
+    }
+
     pub fn start(mut self) -> Machine {
         self.registers.set_pc(0x300);
-        self.running = true;
+        self.set_clock_enabled(true);
         self
     }
-    pub fn write_memory(&mut self, address: u16, value: u16) {
-        self.memory[address as usize] = value;
+    /// Copies an object image into memory and points the PC at its origin.
+    pub fn load_object(&mut self, object: &ObjectFile) {
+        let start = object.origin as usize;
+        self.memory[start..start + object.words.len()].copy_from_slice(&object.words);
+        self.registers.set_pc(object.origin);
+    }
+
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), LoadError> {
+        let object = ObjectFile::read(path)?;
+        self.load_object(&object);
+        Ok(())
+    }
+
+    /// Writes a word as the CPU would, so device registers have their side effects.
+    pub fn write_memory(&mut self, address: u16, value: u16) -> Result<(), MachineError> {
+        self.check_access(address)?;
+        if self.watchpoints.is_empty() {
+            self.write_word(address, value);
+            return Ok(());
+        }
+        let old = self.peek(address);
+        self.write_word(address, value);
+        self.check_watchpoints(address, true, old, value);
+        Ok(())
+    }
+
+    /// Reads a word as the CPU would, so device registers have their side effects.
+    pub fn get_memory(&mut self, address: u16) -> Result<u16, MachineError> {
+        self.check_access(address)?;
+        let value = self.read_word(address);
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(address, false, value, value);
+        }
+        Ok(value)
     }
 
-    pub fn get_memory(&mut self, address: u16) -> u16 {
+    /// The value at an address, including the MCR and device registers, without
+    /// the side effects of reading a device or access control.
+    pub fn peek(&self, address: u16) -> u16 {
+        if address == MCR {
+            return self.mcr;
+        }
+        if let Some(value) = self.devices.peek(address) {
+            return value;
+        }
         self.memory[address as usize]
     }
-    pub fn step(&mut self) {
+
+    fn check_watchpoints(&mut self, address: u16, write: bool, old: u16, new: u16) {
+        let watched = self
+            .watchpoints
+            .iter()
+            .any(|(range, kind)| kind.matches(write) && range.contains(&address));
+        if watched && self.watch_hit.is_none() {
+            self.watch_hit = Some(StopReason::Watchpoint {
+                address,
+                pc: self.fetched_pc,
+                old,
+                new,
+            });
+        }
+    }
+
+    /// Rejects user mode accesses outside user space.
+    fn check_access(&self, address: u16) -> Result<(), MachineError> {
+        if self.registers.is_user_mode() && !USER_SPACE.contains(&address) {
+            return Err(MachineError::AccessViolation {
+                pc: self.fetched_pc,
+                instruction: self.fetched_instruction,
+                address,
+            });
+        }
+        Ok(())
+    }
+
+    //reads and writes on behalf of the system, e.g. by the built-in trap
+    //routines, which are not subject to access control
+    fn write_word(&mut self, address: u16, value: u16) {
+        if address == MCR {
+            self.write_mcr(value);
+            return;
+        }
+        if self.devices.write(address, value, self.console.as_mut()) {
+            return;
+        }
+        let cell = &mut self.memory[address as usize];
+        if let Some(change) = self.change.as_mut() {
+            change.writes.push((address, *cell));
+        }
+        *cell = value;
+    }
+
+    fn read_word(&mut self, address: u16) -> u16 {
+        if address == MCR {
+            return self.mcr;
+        }
+        if let Some(value) = self.devices.read(address, self.console.as_mut()) {
+            return value;
+        }
+        self.memory[address as usize]
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    /// Enters the service routine for `vector` as the hardware does for an
+    /// interrupt or exception: switches to the supervisor stack if running in
+    /// user mode, pushes the PSR and PC, enters supervisor mode and jumps
+    /// through the vector table at x0100. Interrupts also raise the priority.
+    pub fn enter_service_routine(
+        &mut self,
+        vector: u8,
+        priority: Option<u16>,
+    ) -> Result<(), MachineError> {
+        let psr = self.registers.get_psr();
         let pc = self.registers.get_pc();
-        let next_instruction = self.get_memory(pc);
-        self.registers.increment_pc();
-        let opcode: u16 = next_instruction >> 12;
-        let maybe_opcode: Option<Opcodes> = num::FromPrimitive::from_u16(opcode);
-        match maybe_opcode {
-            Some(Opcodes::Branch) => self.branch(next_instruction),
-            Some(Opcodes::Add) => self.add(next_instruction),
-            Some(Opcodes::Load) => self.load(next_instruction),
-            Some(Opcodes::Store) => self.store(next_instruction),
-            Some(Opcodes::JumpRegister) => self.jump_register(next_instruction),
-            Some(Opcodes::And) => self.and(next_instruction),
-            Some(Opcodes::LoadRegister) => self.load_register(next_instruction),
-            Some(Opcodes::StoreRegister) => self.store_register(next_instruction),
-            Some(Opcodes::Rti) => (), //unused
-            Some(Opcodes::Not) => self.not(next_instruction),
-            Some(Opcodes::LoadIndirect) => self.load_indirect(next_instruction),
-            Some(Opcodes::StoreIndirect) => self.store_indirect(next_instruction),
-            Some(Opcodes::Jump) => self.jump(next_instruction),
-            Some(Opcodes::Reserved) => (), //unused
-            Some(Opcodes::LoadEffectiveAddress) => self.load_effective_address(next_instruction),
-            Some(Opcodes::ExecuteTrap) => self.execute_trap(next_instruction),
-            None => panic!("unrecognized opcode"),
-        }
-    }
-    fn immediate_or_source(registers: &Registers, instruction: u16) -> (u16, u16) {
-        let sr = (instruction >> 6) & 0b111;
-        let mode = (instruction >> 5) & 0b1;
-        let op1 = registers.get_by_address(sr);
-
-        let op2 = if mode == 1 {
-            //immediate mode
-            let immediate = util::sign_extend(instruction & 0b11111, 5);
-            immediate as u16
-        } else {
-            let sr2 = instruction & 0b111;
-            registers.get_by_address(sr2)
-        };
-        (op1, op2)
+        let handler = self.read_word(VECTOR_TABLE | vector as u16);
+        if self.registers.is_user_mode() {
+            let usp = self.registers.get_by_name(RegisterName::R6);
+            self.registers.set_saved_usp(usp);
+            let ssp = self.registers.saved_ssp();
+            self.registers.update_by_name(RegisterName::R6, ssp);
+            self.registers.set_user_mode(false);
+        }
+        self.push(psr)?;
+        self.push(pc)?;
+        if let Some(priority) = priority {
+            self.registers.set_priority(priority);
+        }
+        self.registers.set_pc(handler);
+        Ok(())
     }
 
-    fn add(&mut self, instruction: u16) {
-        let dr = (instruction >> 9) & 0b111;
-        let (op1, op2) = Machine::immediate_or_source(&self.registers, instruction);
-        let result = (op1.wrapping_add(op2)) as u16;
-        self.registers
-            .update_by_address_set_condition_flag(dr, result);
+    fn push(&mut self, value: u16) -> Result<(), MachineError> {
+        let sp = self.registers.get_by_name(RegisterName::R6).wrapping_sub(1);
+        self.write_memory(sp, value)?;
+        self.registers.update_by_name(RegisterName::R6, sp);
+        Ok(())
     }
 
-    fn and(&mut self, instruction: u16) {
-        let dr = (instruction >> 9) & 0b111;
-        let (op1, op2) = Machine::immediate_or_source(&self.registers, instruction);
-        let result = (op1 & op2) as u16;
+    /// Pops the PC and PSR pushed by `enter_service_routine`, switching back to
+    /// the user stack if the restored PSR is in user mode.
+    fn return_from_interrupt(&mut self) -> Result<(), MachineError> {
+        let sp = self.registers.get_by_name(RegisterName::R6);
+        let pc = self.get_memory(sp)?;
+        let psr = self.get_memory(sp.wrapping_add(1))?;
         self.registers
-            .update_by_address_set_condition_flag(dr, result);
+            .update_by_name(RegisterName::R6, sp.wrapping_add(2));
+        self.registers.set_pc(pc);
+        self.registers.set_psr(psr);
+        if self.registers.is_user_mode() {
+            let ssp = self.registers.get_by_name(RegisterName::R6);
+            self.registers.set_saved_ssp(ssp);
+            let usp = self.registers.saved_usp();
+            self.registers.update_by_name(RegisterName::R6, usp);
+        }
+        Ok(())
     }
 
-    fn branch(&mut self, instruction: u16) {
-        let nzp = (instruction >> 9) & 0b111;
-        let pc_offset = instruction & 0x1FF;
-        let sign_extended_pc_offset = sign_extend(pc_offset, 9);
-        let cond_flag = self.registers.get_cond_flag();
+    /// All of memory, read directly rather than through the CPU's access path.
+    pub fn memory(&self) -> &[u16] {
+        &self.memory
+    }
+
+    /// All of memory, written directly: no device side effects or access control.
+    pub fn memory_mut(&mut self) -> &mut [u16] {
+        &mut self.memory
+    }
+
+    /// Adds an unconditional breakpoint, returning false if there already is one.
+    pub fn add_breakpoint(&mut self, address: u16) -> bool {
+        if self.breakpoints.contains_key(&address) {
+            return false;
+        }
+        self.breakpoints.insert(address, Breakpoint::default());
+        true
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address).is_some()
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = &u16> {
+        self.breakpoints.keys()
+    }
+
+    pub fn breakpoint(&self, address: u16) -> Option<&Breakpoint> {
+        self.breakpoints.get(&address)
+    }
+
+    pub fn breakpoint_mut(&mut self, address: u16) -> Option<&mut Breakpoint> {
+        self.breakpoints.get_mut(&address)
+    }
+
+    /// Counts a hit if the PC is at a breakpoint whose condition holds, and
+    /// returns whether execution should stop there.
+    pub fn hit_breakpoint(&mut self) -> bool {
+        //keep the common case of no breakpoints cheap
+        if self.breakpoints.is_empty() {
+            return false;
+        }
         let pc = self.registers.get_pc();
-        if cond_flag & nzp > 0 {
-            self.registers.set_pc(pc + sign_extended_pc_offset)
+        let holds = match self.breakpoints.get(&pc) {
+            Some(breakpoint) => breakpoint
+                .condition
+                .as_ref()
+                .map_or(true, |condition| condition.evaluate(self)),
+            None => return false,
+        };
+        match self.breakpoints.get_mut(&pc) {
+            Some(breakpoint) if holds => {
+                breakpoint.hits += 1;
+                breakpoint.hits > breakpoint.ignore_count
+            }
+            _ => false,
         }
     }
 
-    fn load_indirect(&mut self, instruction: u16) {
-        let dr = (instruction >> 9) & 0b111;
-        let pc_offset_9 = instruction & 0x1FF;
-        let sign_extended_pc_offset = util::sign_extend(pc_offset_9, 9);
-        let pointer = self.registers.get_pc() + sign_extended_pc_offset;
-        let address = self.get_memory(pointer);
-        let final_address = self.get_memory(address);
-        self.registers
-            .update_by_address_set_condition_flag(dr, final_address);
+    /// Stops execution after any instruction that accesses `range` in a way
+    /// `kind` matches. Instruction fetches are not watched.
+    pub fn add_watchpoint(&mut self, range: RangeInclusive<u16>, kind: WatchKind) {
+        self.watchpoints.push((range, kind));
     }
 
-    fn store_indirect(&mut self, instruction: u16) {
-        let sr = (instruction >> 9) & 0b111;
-        let pc_offset_9 = instruction & 0x1FF;
-        let sign_extended_pc_offset = util::sign_extend(pc_offset_9, 9);
-        let pointer = self.registers.get_pc() + sign_extended_pc_offset;
-        let address = self.get_memory(pointer);
-        let value = self.registers.get_by_address(sr);
+    /// Removes the watchpoints on exactly `range`, returning whether there were any.
+    pub fn remove_watchpoint(&mut self, range: &RangeInclusive<u16>) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|(watched, _)| watched != range);
+        self.watchpoints.len() != count
+    }
 
-        self.write_memory(address, value);
+    pub fn watchpoints(&self) -> impl Iterator<Item = &(RangeInclusive<u16>, WatchKind)> {
+        self.watchpoints.iter()
     }
 
-    fn load(&mut self, instruction: u16) {
-        let dr = (instruction >> 9) & 0b111;
-        let pc_offset_9 = instruction & 0x1FF;
-        let sign_extended_pc_offset = util::sign_extend(pc_offset_9, 9);
-        let pointer = self.registers.get_pc() + sign_extended_pc_offset;
-        let address = self.get_memory(pointer);
-        self.registers
-            .update_by_address_set_condition_flag(dr, address);
+    /// Captures the full state of the machine. Breakpoints, watchpoints and
+    /// history belong to the debugging session and are not included.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            registers: self.registers.clone(),
+            mcr: self.mcr,
+            host_traps: self.host_traps,
+            memory: self.memory.to_vec(),
+            devices: self.devices.save(),
+        }
+    }
+
+    /// Restores a snapshot taken of a machine with the same devices mapped.
+    /// The machine is left unchanged if it does not fit.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+        if snapshot.memory.len() != self.memory.len() {
+            return Err(SnapshotError::Incompatible(format!(
+                "{} words of memory instead of {}",
+                snapshot.memory.len(),
+                self.memory.len()
+            )));
+        }
+        let devices = self.devices.save();
+        if !self.devices.restore(&snapshot.devices) {
+            self.devices.restore(&devices);
+            return Err(SnapshotError::Incompatible(
+                "the mapped devices differ".to_string(),
+            ));
+        }
+        self.registers = snapshot.registers.clone();
+        self.mcr = snapshot.mcr;
+        self.host_traps = snapshot.host_traps;
+        self.memory.copy_from_slice(&snapshot.memory);
+        self.stopped_at = None;
+        self.clear_history();
+        Ok(())
+    }
+
+    /// Sends a record of every instruction `step` executes to `sink`, or stops
+    /// tracing if it is `None`. Returns the previous sink.
+    pub fn set_trace(&mut self, sink: Option<Box<dyn TraceSink>>) -> Option<Box<dyn TraceSink>> {
+        std::mem::replace(&mut self.trace, sink)
+    }
+
+    /// Starts recording an undo log of up to `limit` instructions, so that
+    /// execution can be reversed. Side effects on devices, such as console
+    /// input and output, are not undone.
+    pub fn record_history(&mut self, limit: usize) {
+        self.history = Some(History::new(limit));
+    }
+
+    pub fn stop_recording(&mut self) {
+        self.history = None;
+    }
+
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+
+    /// Forgets the recorded history, keeping the recording on. Call it after
+    /// changing the machine other than by executing instructions.
+    pub fn clear_history(&mut self) {
+        if let Some(history) = self.history.as_mut() {
+            *history = History::new(history.limit());
+        }
+    }
+
+    /// Writes a word through the device bus on behalf of a debugger, without
+    /// access control or watchpoints. The history is cleared, as the write
+    /// cannot be undone.
+    pub fn poke(&mut self, address: u16, value: u16) {
+        self.write_word(address, value);
+        self.clear_history();
+    }
+
+    /// Undoes the last recorded instruction, returning false if there is none.
+    pub fn step_back(&mut self) -> bool {
+        let change = match self.history.as_mut().and_then(History::pop) {
+            Some(change) => change,
+            None => return false,
+        };
+        for &(address, old) in change.writes.iter().rev() {
+            if address == MCR {
+                self.mcr = old;
+            } else {
+                self.memory[address as usize] = old;
+            }
+        }
+        self.registers = change.registers;
+        true
+    }
+
+    /// Undoes instructions until the PC is at a breakpoint whose condition
+    /// holds, an instruction that wrote to a write or access watchpoint has
+    /// been undone, or the history runs out. Hit counts are not affected.
+    pub fn run_backward(&mut self) -> StopReason {
+        loop {
+            let hit = match self.history.as_ref().and_then(History::last) {
+                Some(change) => self.watched_write(change),
+                None => return StopReason::HistoryStart,
+            };
+            self.step_back();
+            if let Some(hit) = hit {
+                return hit;
+            }
+            let pc = self.registers.get_pc();
+            let stop = self.breakpoints.get(&pc).is_some_and(|breakpoint| {
+                breakpoint
+                    .condition
+                    .as_ref()
+                    .map_or(true, |condition| condition.evaluate(self))
+            });
+            if stop {
+                self.stopped_at = Some(pc);
+                return StopReason::Breakpoint(pc);
+            }
+        }
+    }
+
+    //the first write `change` made to a watched address, as a stop reason
+    fn watched_write(&self, change: &Change) -> Option<StopReason> {
+        change.writes.iter().find_map(|&(address, old)| {
+            let watched = self
+                .watchpoints
+                .iter()
+                .any(|(range, kind)| kind.matches(true) && range.contains(&address));
+            if !watched {
+                return None;
+            }
+            Some(StopReason::Watchpoint {
+                address,
+                pc: change.registers.get_pc(),
+                old,
+                new: self.peek(address),
+            })
+        })
+    }
+
+    /// The watchpoint triggered by the last instruction [`Machine::step`]
+    /// executed, if any. It is only reported once.
+    pub fn take_watch_hit(&mut self) -> Option<StopReason> {
+        self.watch_hit.take()
+    }
+
+    /// Executes instructions until the machine halts or reaches a breakpoint.
+    pub fn run(&mut self) -> StopReason {
+        self.run_with_limit(None)
     }
 
-    fn store(&mut self, instruction: u16) {
-        let sr = (instruction >> 9) & 0b111;
-        let pc_offset_9 = instruction & 0x1FF;
-        let sign_extended_pc_offset = util::sign_extend(pc_offset_9, 9);
-        let pointer = self.registers.get_by_name(RegisterName::Pc) + sign_extended_pc_offset;
-        let register_value = self.registers.get_by_address(sr);
-        self.memory[pointer as usize] = register_value;
-        self.write_memory(pointer, register_value);
+    /// Like [`Machine::run`], but executes at most `steps` instructions.
+    pub fn run_for(&mut self, steps: u64) -> StopReason {
+        self.run_with_limit(Some(steps))
     }
 
-    fn jump_register(&mut self, instruction: u16) {
-        let mode = (instruction >> 11) & 1;
+    fn run_with_limit(&mut self, limit: Option<u64>) -> StopReason {
+        let mut executed = 0;
+        let resumed_from = self.stopped_at;
+        while self.is_running() {
+            if limit.is_some_and(|limit| executed >= limit) {
+                return StopReason::StepLimit;
+            }
+            let pc = self.registers.get_pc();
+            let resuming = executed == 0 && resumed_from == Some(pc);
+            if !resuming && self.hit_breakpoint() {
+                self.stopped_at = Some(pc);
+                return StopReason::Breakpoint(pc);
+            }
+            //only executing an instruction moves past the breakpoint
+            self.stopped_at = None;
+            if let Err(error) = self.step() {
+                return StopReason::Fault(error);
+            }
+            executed += 1;
+            if let Some(hit) = self.watch_hit.take() {
+                return hit;
+            }
+        }
+        StopReason::Halted
+    }
+    /// Executes a single instruction. A fault enters the handler installed in
+    /// the vector table (a zero entry means none is installed); if there is
+    /// none, or entering it fails, the fault is returned and the PC is
+    /// restored to the faulting instruction.
+    pub fn step(&mut self) -> Result<(), MachineError> {
+        if self.history.is_none() && self.trace.is_none() {
+            return self.execute_step();
+        }
+        self.change = Some(Change::new(self.registers.clone()));
+        let result = self.execute_step();
+        //a fault that is returned leaves nothing to undo or trace
+        if let (Ok(()), Some(change)) = (&result, self.change.take()) {
+            if self.trace.is_some() {
+                let record = self.trace_record(&change);
+                if let Some(trace) = self.trace.as_mut() {
+                    trace.record(&record);
+                }
+            }
+            if let Some(history) = self.history.as_mut() {
+                history.push(change);
+            }
+        }
+        result
+    }
+
+    fn trace_record(&self, change: &Change) -> TraceRecord {
+        let before = &change.registers;
+        let mut registers: Vec<(RegisterName, u16)> = (0..8)
+            .map(RegisterName::from_field)
+            .filter(|&register| {
+                before.get_by_name(register) != self.registers.get_by_name(register)
+            })
+            .map(|register| (register, self.registers.get_by_name(register)))
+            .collect();
+        let psr = self.registers.get_psr();
+        if psr & !0b111 != before.get_psr() & !0b111 {
+            registers.push((RegisterName::Psr, psr));
+        }
+        let mut writes: Vec<(u16, u16)> = Vec::new();
+        for &(address, _) in &change.writes {
+            if writes.iter().all(|&(written, _)| written != address) {
+                writes.push((address, self.peek(address)));
+            }
+        }
+        TraceRecord {
+            pc: self.fetched_pc,
+            instruction: self.fetched_instruction,
+            registers,
+            condition: psr & 0b111,
+            writes,
+        }
+    }
+
+    fn execute_step(&mut self) -> Result<(), MachineError> {
+        let pc = self.registers.get_pc();
+        self.fetched_pc = pc;
+        self.fetched_instruction = 0;
+        self.watch_hit = None;
+        if let Err(error) = self.fetch_and_execute() {
+            let vector = error.exception_vector();
+            if !self.has_handler(vector) {
+                self.registers.set_pc(pc);
+                return Err(error);
+            }
+            //as in hardware, the saved PC is past the faulting instruction
+            //unless the fetch itself faulted
+            let registers = self.registers.clone();
+            if let Err(error) = self.enter_service_routine(vector, None) {
+                self.registers = registers;
+                self.registers.set_pc(pc);
+                return Err(error);
+            }
+        }
+        self.devices.tick(self.console.as_mut());
+        self.service_interrupts()
+    }
+
+    /// Whether a handler is installed for `vector`. An entry of zero in the
+    /// vector table means there is none, so a handler cannot start at x0000.
+    fn has_handler(&self, vector: u8) -> bool {
+        self.memory[(VECTOR_TABLE | vector as u16) as usize] != 0
+    }
+
+    fn fetch_and_execute(&mut self) -> Result<(), MachineError> {
+        self.check_access(self.fetched_pc)?;
+        let next_instruction = self.read_word(self.fetched_pc);
+        self.fetched_instruction = next_instruction;
+        self.registers.increment_pc();
+        self.execute(next_instruction)
+    }
+
+    /// Takes the pending interrupt, if any, when it outranks the running program.
+    fn service_interrupts(&mut self) -> Result<(), MachineError> {
+        match self.devices.pending_interrupt() {
+            Some(request) if request.priority() > self.registers.priority() => {
+                self.enter_service_routine(request.vector(), Some(request.priority()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn execute(&mut self, next_instruction: u16) -> Result<(), MachineError> {
+        let pc = self.fetched_pc;
+        //the CPU ignores unused bits, so nonstandard encodings still execute
+        match Instruction::decode_fields(next_instruction) {
+            Instruction::Br { nzp, offset9 } => self.branch(nzp, offset9),
+            Instruction::Add { dr, sr1, operand } => self.add(dr, sr1, operand),
+            Instruction::Ld { dr, offset9 } => self.load(dr, offset9)?,
+            Instruction::St { sr, offset9 } => self.store(sr, offset9)?,
+            Instruction::Jsr { offset11 } => {
+                let address = self.registers.get_pc().wrapping_add(offset11 as u16);
+                self.jump_subroutine(address)
+            }
+            Instruction::Jsrr { base } => {
+                //the target is read before R7 is linked so that JSRR R7 jumps to the old R7
+                let address = self.registers.get_by_name(base);
+                self.jump_subroutine(address)
+            }
+            Instruction::And { dr, sr1, operand } => self.and(dr, sr1, operand),
+            Instruction::Ldr { dr, base, offset6 } => self.load_register(dr, base, offset6)?,
+            Instruction::Str { sr, base, offset6 } => self.store_register(sr, base, offset6)?,
+            Instruction::Rti if self.registers.is_user_mode() => {
+                return Err(MachineError::PrivilegeViolation {
+                    pc,
+                    instruction: next_instruction,
+                });
+            }
+            Instruction::Rti => self.return_from_interrupt()?,
+            Instruction::Not { dr, sr } => self.not(dr, sr),
+            Instruction::Ldi { dr, offset9 } => self.load_indirect(dr, offset9)?,
+            Instruction::Sti { sr, offset9 } => self.store_indirect(sr, offset9)?,
+            Instruction::Jmp { base } => self.jump(base),
+            Instruction::Reserved(_) => {
+                return Err(MachineError::ReservedOpcode {
+                    pc,
+                    instruction: next_instruction,
+                });
+            }
+            Instruction::Lea { dr, offset9 } => self.load_effective_address(dr, offset9),
+            Instruction::Trap { trapvect8 } => self.execute_trap(trapvect8),
+            Instruction::Nonstandard(_) => unreachable!("decode_fields keeps no raw words"),
+        }
+        Ok(())
+    }
+
+    fn operand_value(&self, operand: Operand) -> u16 {
+        match operand {
+            Operand::Register(sr2) => self.registers.get_by_name(sr2),
+            Operand::Immediate(imm5) => imm5 as u16,
+        }
+    }
+
+    /// The address `offset` words from the incremented PC.
+    fn pc_relative(&self, offset: i16) -> u16 {
+        self.registers.get_pc().wrapping_add(offset as u16)
+    }
+
+    fn add(&mut self, dr: RegisterName, sr1: RegisterName, operand: Operand) {
+        let op1 = self.registers.get_by_name(sr1);
+        let result = op1.wrapping_add(self.operand_value(operand));
+        self.registers.update_by_name_set_condition_flag(dr, result);
+    }
+
+    fn and(&mut self, dr: RegisterName, sr1: RegisterName, operand: Operand) {
+        let op1 = self.registers.get_by_name(sr1);
+        let result = op1 & self.operand_value(operand);
+        self.registers.update_by_name_set_condition_flag(dr, result);
+    }
+
+    fn branch(&mut self, nzp: u8, offset9: i16) {
+        let cond_flag = self.registers.get_cond_flag();
+        if cond_flag & nzp as u16 > 0 {
+            let address = self.pc_relative(offset9);
+            self.registers.set_pc(address)
+        }
+    }
+
+    fn load_indirect(&mut self, dr: RegisterName, offset9: i16) -> Result<(), MachineError> {
+        let pointer = self.pc_relative(offset9);
+        let address = self.get_memory(pointer)?;
+        let value = self.get_memory(address)?;
+        self.registers.update_by_name_set_condition_flag(dr, value);
+        Ok(())
+    }
+
+    fn store_indirect(&mut self, sr: RegisterName, offset9: i16) -> Result<(), MachineError> {
+        let pointer = self.pc_relative(offset9);
+        let address = self.get_memory(pointer)?;
+        let value = self.registers.get_by_name(sr);
+        self.write_memory(address, value)
+    }
+
+    fn load(&mut self, dr: RegisterName, offset9: i16) -> Result<(), MachineError> {
+        let address = self.pc_relative(offset9);
+        let value = self.get_memory(address)?;
+        self.registers.update_by_name_set_condition_flag(dr, value);
+        Ok(())
+    }
+
+    fn store(&mut self, sr: RegisterName, offset9: i16) -> Result<(), MachineError> {
+        let address = self.pc_relative(offset9);
+        let register_value = self.registers.get_by_name(sr);
+        self.write_memory(address, register_value)
+    }
+
+    fn jump_subroutine(&mut self, address: u16) {
         let pc = self.registers.get_pc();
         self.registers.update_by_name(RegisterName::R7, pc);
-        let address = if mode == 1 {
-            let immediate = instruction & 0x7FF;
-            let sign_extended_offset = util::sign_extend(immediate, 11);
-            pc.wrapping_add(sign_extended_offset)
-        } else {
-            let register = (instruction >> 6) & 0b111;
-            self.registers.get_by_address(register)
-        };
         self.registers.set_pc(address);
     }
 
-    fn jump(&mut self, instruction: u16) {
-        let base_r = (instruction >> 6) & 0b111;
-        let address = if base_r == 0b111 {
-            //return
-            self.registers.get_by_name(RegisterName::R7)
-        } else {
-            //jump
-            self.registers.get_by_address(base_r)
-        };
+    fn jump(&mut self, base: RegisterName) {
+        //RET is JMP R7
+        let address = self.registers.get_by_name(base);
         self.registers.set_pc(address)
     }
 
-    fn not(&mut self, instruction: u16) {
-        let source = (instruction >> 6) & 0b111;
-        let destination = (instruction >> 9) & 0b111;
-        let register_value = self.registers.get_by_address(source);
+    fn not(&mut self, dr: RegisterName, sr: RegisterName) {
+        let register_value = self.registers.get_by_name(sr);
         self.registers
-            .update_by_address_set_condition_flag(destination, !register_value);
+            .update_by_name_set_condition_flag(dr, !register_value);
     }
 
-    fn load_register(&mut self, instruction: u16) {
-        let dr = (instruction >> 9) & 0b111;
-        let base_r = (instruction >> 6) & 0b111;
-        let offset_6 = sign_extend(instruction & 0x3F, 6);
-        let base_address = self.registers.get_by_address(base_r);
-        let address = offset_6.wrapping_add(base_address);
-        let cell = self.get_memory(address);
-        self.registers
-            .update_by_address_set_condition_flag(dr, cell);
+    fn load_register(
+        &mut self,
+        dr: RegisterName,
+        base: RegisterName,
+        offset6: i16,
+    ) -> Result<(), MachineError> {
+        let base_address = self.registers.get_by_name(base);
+        let address = base_address.wrapping_add(offset6 as u16);
+        let cell = self.get_memory(address)?;
+        self.registers.update_by_name_set_condition_flag(dr, cell);
+        Ok(())
+    }
+
+    fn store_register(
+        &mut self,
+        sr: RegisterName,
+        base: RegisterName,
+        offset6: i16,
+    ) -> Result<(), MachineError> {
+        let base_address = self.registers.get_by_name(base);
+        let address = base_address.wrapping_add(offset6 as u16);
+        let source_value = self.registers.get_by_name(sr);
+        self.write_memory(address, source_value)
     }
 
-    fn store_register(&mut self, instruction: u16) {
-        let sr = (instruction >> 9) & 0b111;
-        let base_r = (instruction >> 6) & 0b111;
-        let offset_6 = sign_extend(instruction & 0x3F, 6);
-        let base_address = self.registers.get_by_address(base_r);
-        let address = base_address.wrapping_add(offset_6);
-        let source_value = self.registers.get_by_address(sr);
-        self.write_memory(address, source_value);
+    fn load_effective_address(&mut self, dr: RegisterName, offset9: i16) {
+        let address = self.pc_relative(offset9);
+        //LEA only computes the address; memory is not read. The current ISA,
+        //like the reference simulator, leaves the condition codes alone.
+        self.registers.update_by_name(dr, address);
     }
 
-    fn load_effective_address(&mut self, instruction: u16) {
+    fn execute_trap(&mut self, trapvect8: u8) {
         let pc = self.registers.get_pc();
-        let trap_vect_8 = sign_extend(instruction & 0xFF, 8);
         self.registers.update_by_name(RegisterName::R7, pc);
-        let address = self.get_memory(trap_vect_8);
-        self.registers.set_pc(address)
+        let maybe_trap: Option<Traps> = if self.host_traps {
+            num::FromPrimitive::from_u8(trapvect8)
+        } else {
+            None
+        };
+        match maybe_trap {
+            Some(Traps::Getc) => self.trap_getc(),
+            Some(Traps::Out) => self.trap_out(),
+            Some(Traps::Puts) => self.trap_puts(),
+            Some(Traps::In) => self.trap_in(),
+            Some(Traps::Putsp) => self.trap_putsp(),
+            Some(Traps::Halt) => self.trap_halt(),
+            None => {
+                //not a built-in routine, so dispatch through the trap vector table
+                let address = self.read_word(trapvect8 as u16);
+                self.registers.set_pc(address)
+            }
+        }
     }
 
-    fn execute_trap(&mut self, instruction: u16) {
-        let dr = (instruction >> 9) & 0b111;
-        let pc_offset_9 = sign_extend(instruction & 0x1FF, 9);
-        let pc = self.registers.get_pc();
-        let address = pc.wrapping_add(pc_offset_9);
-        let cell = self.get_memory(address);
-        self.registers
-            .update_by_address_set_condition_flag(dr, cell);
+    /// Reads a character into R0, stopping the machine if input is exhausted.
+    fn read_into_r0(&mut self) {
+        match self.console.read_char() {
+            Some(c) => self
+                .registers
+                .update_by_name_set_condition_flag(RegisterName::R0, c as u16),
+            None => self.set_clock_enabled(false),
+        }
+    }
+
+    fn write_string(&mut self, s: &str) {
+        for c in s.bytes() {
+            self.console.write_char(c);
+        }
+    }
+
+    fn trap_getc(&mut self) {
+        self.read_into_r0();
+    }
+
+    fn trap_out(&mut self) {
+        let c = self.registers.get_by_name(RegisterName::R0) as u8;
+        self.console.write_char(c);
+        self.console.flush();
+    }
+
+    fn trap_puts(&mut self) {
+        let mut address = self.registers.get_by_name(RegisterName::R0);
+        loop {
+            let word = self.read_word(address);
+            if word == 0 {
+                break;
+            }
+            self.console.write_char(word as u8);
+            address = address.wrapping_add(1);
+        }
+        self.console.flush();
+    }
+
+    fn trap_in(&mut self) {
+        self.write_string("\nInput a character> ");
+        self.console.flush();
+        self.read_into_r0();
+        if self.is_running() {
+            let c = self.registers.get_by_name(RegisterName::R0) as u8;
+            self.console.write_char(c);
+            self.console.flush();
+        }
+    }
+
+    fn trap_putsp(&mut self) {
+        let mut address = self.registers.get_by_name(RegisterName::R0);
+        'words: loop {
+            let word = self.read_word(address);
+            for c in [word as u8, (word >> 8) as u8] {
+                if c == 0 {
+                    break 'words;
+                }
+                self.console.write_char(c);
+            }
+            address = address.wrapping_add(1);
+        }
+        self.console.flush();
+    }
+
+    fn trap_halt(&mut self) {
+        self.write_string("\n--- halting the LC-3 ---\n");
+        self.console.flush();
+        self.set_clock_enabled(false);
     }
 }
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::console::BufferConsole;
     use crate::instruction_builder::instructions::{
-        add, add_immediate, and_immediate, and_register, branch, decrement, increment, jump_offset,
-        jump_register, load, load_indirect, load_register, not, store, store_register,
+        add, and_immediate, and_register, branch, decrement, increment, jump_offset, jump_register,
+        load, load_indirect, load_register, not, store, store_register, trap,
     };
     use crate::registers::RegisterName;
+    use std::cell::RefCell;
+    use std::rc::Rc;
     #[test]
     fn it_can_add_in_immediate_mode() {
         let mut machine = Machine::empty().start();
         let increment_r1 = increment(RegisterName::R1);
         let decrement_r1 = decrement(RegisterName::R1);
-        machine.add(increment_r1);
-        machine.add(increment_r1);
-        machine.add(increment_r1);
-        machine.add(decrement_r1);
+        machine.execute(increment_r1).unwrap();
+        machine.execute(increment_r1).unwrap();
+        machine.execute(increment_r1).unwrap();
+        machine.execute(decrement_r1).unwrap();
         assert_eq!(machine.registers.get_by_name(RegisterName::R1), 2);
     }
 
@@ -239,8 +997,8 @@ mod tests {
     fn it_can_represent_adding_twos_complement_resulting_in_overflow() {
         let mut machine = Machine::empty().start();
         let decrement_r1 = decrement(RegisterName::R1);
-        machine.add(decrement_r1);
-        machine.add(decrement_r1);
+        machine.execute(decrement_r1).unwrap();
+        machine.execute(decrement_r1).unwrap();
         assert_eq!(machine.registers.get_by_name(RegisterName::R1) as i16, -2);
     }
 
@@ -249,14 +1007,14 @@ mod tests {
         let mut machine = Machine::empty().start();
         let increment_r1 = increment(RegisterName::R1);
         let increment_r2 = increment(RegisterName::R2);
-        machine.add(increment_r1);
-        machine.add(increment_r1);
-        machine.add(increment_r1);
-        machine.add(increment_r2);
-        machine.add(increment_r2);
+        machine.execute(increment_r1).unwrap();
+        machine.execute(increment_r1).unwrap();
+        machine.execute(increment_r1).unwrap();
+        machine.execute(increment_r2).unwrap();
+        machine.execute(increment_r2).unwrap();
 
         let add_r1_r2_place_in_r3 = add(RegisterName::R1, RegisterName::R2, RegisterName::R3);
-        machine.add(add_r1_r2_place_in_r3);
+        machine.execute(add_r1_r2_place_in_r3).unwrap();
         assert_eq!(machine.registers.get_by_name(RegisterName::R3), 5);
     }
 
@@ -267,7 +1025,7 @@ mod tests {
             .registers
             .update_by_name_set_condition_flag(RegisterName::R1, 0b11);
         let and_r1 = and_immediate(RegisterName::R1, 0b1);
-        machine.and(and_r1);
+        machine.execute(and_r1).unwrap();
         assert_eq!(machine.registers.get_by_name(RegisterName::R1), 1);
     }
 
@@ -282,7 +1040,7 @@ mod tests {
             .registers
             .update_by_name_set_condition_flag(RegisterName::R2, 0b11);
         let and_r1 = and_register(RegisterName::R1, RegisterName::R2, RegisterName::R3);
-        machine.and(and_r1);
+        machine.execute(and_r1).unwrap();
         assert_eq!(machine.registers.get_by_name(RegisterName::R3), 0b11);
     }
 
@@ -291,9 +1049,9 @@ mod tests {
         let mut machine = Machine::empty().start();
         let increment_r1 = increment(RegisterName::R1);
         let store_r1_in_mem100 = store(RegisterName::R1, 100);
-        machine.add(increment_r1);
-        machine.add(increment_r1);
-        machine.store(store_r1_in_mem100);
+        machine.execute(increment_r1).unwrap();
+        machine.execute(increment_r1).unwrap();
+        machine.execute(store_r1_in_mem100).unwrap();
 
         let final_address = machine.registers.get_by_name(RegisterName::Pc) + 100;
         assert_eq!(machine.memory[final_address as usize], 2);
@@ -305,7 +1063,7 @@ mod tests {
         let load_memory_address = load(RegisterName::R1, 200);
         let pc = machine.registers.get_by_name(RegisterName::Pc);
         machine.memory[(pc + 200) as usize] = 42;
-        machine.load(load_memory_address);
+        machine.execute(load_memory_address).unwrap();
 
         assert_eq!(machine.registers.get_by_name(RegisterName::R1), 42);
     }
@@ -317,7 +1075,7 @@ mod tests {
         let pc = machine.registers.get_by_name(RegisterName::Pc);
         machine.memory[(pc + 200) as usize] = 42;
         machine.memory[42] = 99;
-        machine.load_indirect(load_memory_address);
+        machine.execute(load_memory_address).unwrap();
 
         assert_eq!(machine.registers.get_by_name(RegisterName::R1), 99);
     }
@@ -329,7 +1087,7 @@ mod tests {
         let pc_before = machine.registers.get_pc();
         assert_eq!(pc_before, 0x42);
         let jump_immediate_instruction = jump_offset(200);
-        machine.jump_register(jump_immediate_instruction);
+        machine.execute(jump_immediate_instruction).unwrap();
         let pc_after = machine.registers.get_pc();
 
         assert_eq!(machine.registers.get_by_name(RegisterName::R7), pc_before);
@@ -345,7 +1103,7 @@ mod tests {
             .update_by_name_set_condition_flag(RegisterName::R3, 42);
         let pc_before = machine.registers.get_pc();
         let jump_register_instruction = jump_register(RegisterName::R3);
-        machine.jump_register(jump_register_instruction);
+        machine.execute(jump_register_instruction).unwrap();
         let pc_after = machine.registers.get_pc();
 
         assert_eq!(machine.registers.get_by_name(RegisterName::R7), pc_before);
@@ -359,7 +1117,7 @@ mod tests {
             .registers
             .update_by_name_set_condition_flag(RegisterName::R3, 0xFF);
         let not_instruction = not(RegisterName::R3, RegisterName::R4);
-        machine.not(not_instruction);
+        machine.execute(not_instruction).unwrap();
         let result = machine.registers.get_by_name(RegisterName::R4);
 
         assert_eq!(result, 0xFF00);
@@ -376,20 +1134,33 @@ mod tests {
         let jump_neg = branch(crate::registers::ConditionFlag::Negative, 3);
         let jump_zero = branch(crate::registers::ConditionFlag::Zero, 5);
         let jump_pos = branch(crate::registers::ConditionFlag::Positive, 7);
-        machine.branch(jump_neg);
+        machine.execute(jump_neg).unwrap();
         let pc_after = machine.registers.get_pc();
 
         assert_eq!(pc_before + 3, pc_after);
         let pc_before = machine.registers.get_pc();
-        machine.branch(jump_zero);
+        machine.execute(jump_zero).unwrap();
         let pc_after = machine.registers.get_pc();
         assert_eq!(pc_before, pc_after);
 
-        machine.branch(jump_pos);
+        machine.execute(jump_pos).unwrap();
         let pc_after = machine.registers.get_pc();
         assert_eq!(pc_before, pc_after);
     }
 
+    #[test]
+    fn it_can_load_an_object_image() {
+        let mut machine = Machine::empty().start();
+        let object = ObjectFile {
+            origin: 0x3000,
+            words: vec![0x1234, 0x5678],
+        };
+        machine.load_object(&object);
+        assert_eq!(machine.registers.get_pc(), 0x3000);
+        assert_eq!(machine.get_memory(0x3000).unwrap(), 0x1234);
+        assert_eq!(machine.get_memory(0x3001).unwrap(), 0x5678);
+    }
+
     #[test]
     fn it_can_load_register() {
         let mut machine = Machine::empty().start();
@@ -397,9 +1168,9 @@ mod tests {
             .registers
             .update_by_name_set_condition_flag(RegisterName::R1, 10); //base register
 
-        machine.write_memory(9, 99);
+        machine.write_memory(9, 99).unwrap();
         let load_register_instruction = load_register(RegisterName::R1, 0b111111, RegisterName::R2);
-        machine.load_register(load_register_instruction);
+        machine.execute(load_register_instruction).unwrap();
         let destination = machine.registers.get_by_name(RegisterName::R2);
         assert_eq!(destination, 99);
     }
@@ -411,8 +1182,1008 @@ mod tests {
 
         machine.registers.update_by_name(RegisterName::R2, 42);
         let store_register_instruction = store_register(RegisterName::R1, 1, RegisterName::R2);
-        machine.store_register(store_register_instruction);
-        let cell = machine.get_memory(11);
+        machine.execute(store_register_instruction).unwrap();
+        let cell = machine.get_memory(11).unwrap();
         assert_eq!(cell, 42);
     }
+
+    fn machine_with_console(input: &[u8]) -> (Machine, BufferConsole) {
+        let console = BufferConsole::new(input);
+        let machine = Machine::empty()
+            .with_console(Box::new(console.clone()))
+            .start();
+        (machine, console)
+    }
+
+    fn execute(machine: &mut Machine, instruction: u16) {
+        let pc = machine.registers.get_pc();
+        machine.write_memory(pc, instruction).unwrap();
+        machine.step().unwrap();
+    }
+
+    #[test]
+    fn it_links_r7_and_returns_after_a_trap() {
+        let (mut machine, _console) = machine_with_console(b"");
+        let pc_before = machine.registers.get_pc();
+        execute(&mut machine, trap(0x21));
+        assert_eq!(
+            machine.registers.get_by_name(RegisterName::R7),
+            pc_before + 1
+        );
+        assert_eq!(machine.registers.get_pc(), pc_before + 1);
+    }
+
+    #[test]
+    fn it_dispatches_unknown_traps_through_the_vector_table() {
+        let (mut machine, _console) = machine_with_console(b"");
+        machine.write_memory(0x30, 0x4000).unwrap();
+        execute(&mut machine, trap(0x30));
+        assert_eq!(machine.registers.get_pc(), 0x4000);
+        assert_eq!(machine.registers.get_by_name(RegisterName::R7), 0x301);
+    }
+
+    #[test]
+    fn it_can_trap_getc_without_echo() {
+        let (mut machine, console) = machine_with_console(b"a");
+        execute(&mut machine, trap(0x20));
+        assert_eq!(machine.registers.get_by_name(RegisterName::R0), b'a' as u16);
+        assert!(console.output().is_empty());
+    }
+
+    #[test]
+    fn it_stops_when_getc_runs_out_of_input() {
+        let (mut machine, _console) = machine_with_console(b"");
+        execute(&mut machine, trap(0x20));
+        assert!(!machine.is_running());
+    }
+
+    #[test]
+    fn it_can_trap_out() {
+        let (mut machine, console) = machine_with_console(b"");
+        machine
+            .registers
+            .update_by_name(RegisterName::R0, b'!' as u16);
+        execute(&mut machine, trap(0x21));
+        assert_eq!(console.output_string(), "!");
+    }
+
+    #[test]
+    fn it_can_trap_puts() {
+        let (mut machine, console) = machine_with_console(b"");
+        for (offset, c) in b"hi\0".iter().enumerate() {
+            machine
+                .write_memory(0x4000 + offset as u16, *c as u16)
+                .unwrap();
+        }
+        machine.registers.update_by_name(RegisterName::R0, 0x4000);
+        execute(&mut machine, trap(0x22));
+        assert_eq!(console.output_string(), "hi");
+    }
+
+    #[test]
+    fn it_can_trap_in_with_prompt_and_echo() {
+        let (mut machine, console) = machine_with_console(b"z");
+        execute(&mut machine, trap(0x23));
+        assert_eq!(machine.registers.get_by_name(RegisterName::R0), b'z' as u16);
+        assert_eq!(console.output_string(), "\nInput a character> z");
+    }
+
+    #[test]
+    fn it_can_trap_putsp() {
+        let (mut machine, console) = machine_with_console(b"");
+        machine
+            .write_memory(0x4000, (b'e' as u16) << 8 | b'h' as u16)
+            .unwrap();
+        machine
+            .write_memory(0x4001, (b'l' as u16) << 8 | b'l' as u16)
+            .unwrap();
+        machine.write_memory(0x4002, b'o' as u16).unwrap();
+        machine.registers.update_by_name(RegisterName::R0, 0x4000);
+        execute(&mut machine, trap(0x24));
+        assert_eq!(console.output_string(), "hello");
+    }
+
+    #[test]
+    fn it_can_trap_halt() {
+        let (mut machine, console) = machine_with_console(b"");
+        assert!(machine.is_running());
+        execute(&mut machine, trap(0x25));
+        assert!(!machine.is_running());
+        assert!(console.output_string().contains("halting"));
+    }
+
+    #[test]
+    fn it_runs_until_halt() {
+        let (mut machine, console) = machine_with_console(b"");
+        machine
+            .write_memory(0x300, increment(RegisterName::R1))
+            .unwrap();
+        machine.write_memory(0x301, trap(0x25)).unwrap();
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(machine.registers.get_by_name(RegisterName::R1), 1);
+        assert!(console.output_string().contains("halting"));
+        assert_eq!(machine.run(), StopReason::Halted);
+    }
+
+    #[test]
+    fn it_runs_for_a_step_budget() {
+        let (mut machine, _console) = machine_with_console(b"");
+        machine
+            .write_memory(0x300, branch(crate::registers::ConditionFlag::Zero, 0x1FF))
+            .unwrap();
+        assert_eq!(machine.run_for(10), StopReason::StepLimit);
+        assert_eq!(machine.registers.get_pc(), 0x300);
+        assert!(machine.is_running());
+    }
+
+    #[test]
+    fn it_stops_at_breakpoints_and_resumes_past_them() {
+        let (mut machine, _console) = machine_with_console(b"");
+        for address in 0x300..0x303 {
+            machine
+                .write_memory(address, increment(RegisterName::R1))
+                .unwrap();
+        }
+        machine.write_memory(0x303, trap(0x25)).unwrap();
+        assert!(machine.add_breakpoint(0x302));
+        assert_eq!(machine.run(), StopReason::Breakpoint(0x302));
+        assert_eq!(machine.registers.get_by_name(RegisterName::R1), 2);
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(machine.registers.get_by_name(RegisterName::R1), 3);
+    }
+
+    #[test]
+    fn it_resumes_past_a_breakpoint_after_an_empty_run() {
+        let (mut machine, _console) = machine_with_console(b"");
+        machine
+            .write_memory(0x300, increment(RegisterName::R1))
+            .unwrap();
+        machine.write_memory(0x301, trap(0x25)).unwrap();
+        machine.add_breakpoint(0x300);
+        assert_eq!(machine.run(), StopReason::Breakpoint(0x300));
+        assert_eq!(machine.run_for(0), StopReason::StepLimit);
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(machine.registers.get_by_name(RegisterName::R1), 1);
+    }
+
+    #[test]
+    fn it_stops_at_a_breakpoint_on_the_first_instruction() {
+        let (mut machine, _console) = machine_with_console(b"");
+        machine.write_memory(0x300, trap(0x25)).unwrap();
+        machine.add_breakpoint(0x300);
+        assert_eq!(machine.run(), StopReason::Breakpoint(0x300));
+        assert!(machine.remove_breakpoint(0x300));
+        assert!(!machine.remove_breakpoint(0x300));
+        assert_eq!(machine.run(), StopReason::Halted);
+    }
+
+    #[test]
+    fn it_stops_at_breakpoints_whose_condition_holds_past_the_ignore_count() {
+        let program = crate::assembler::assemble(
+            ".ORIG x3000
+                 AND R1, R1, #0
+                 ADD R2, R1, #5
+            LOOP ADD R1, R1, #1
+                 ADD R2, R2, #-1
+                 BRp LOOP
+                 HALT
+            .END",
+        )
+        .unwrap();
+        let (mut machine, _console) = machine_with_console(b"");
+        machine.load_object(&program.object);
+        machine.add_breakpoint(0x3002);
+        machine.breakpoint_mut(0x3002).unwrap().condition =
+            Some(Condition::parse("R1 >= 2", None).unwrap());
+        assert_eq!(machine.run(), StopReason::Breakpoint(0x3002));
+        assert_eq!(machine.registers.get_by_name(RegisterName::R1), 2);
+
+        machine.breakpoint_mut(0x3002).unwrap().ignore_count = 2;
+        assert_eq!(machine.run(), StopReason::Breakpoint(0x3002));
+        assert_eq!(machine.registers.get_by_name(RegisterName::R1), 4);
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(machine.breakpoint(0x3002).unwrap().hits, 3);
+    }
+
+    fn counter_machine() -> Machine {
+        let program = crate::assembler::assemble(
+            ".ORIG x3000
+                 LD R1, COUNT
+                 ADD R1, R1, #1
+                 ST R1, COUNT
+                 ST R1, COUNT
+                 HALT
+            COUNT .FILL #5
+            .END",
+        )
+        .unwrap();
+        let (mut machine, _console) = machine_with_console(b"");
+        machine.load_object(&program.object);
+        machine
+    }
+
+    #[test]
+    fn it_steps_back_through_recorded_history() {
+        let mut machine = counter_machine();
+        machine.record_history(100);
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(machine.history().unwrap().len(), 5);
+        assert_eq!(machine.memory[0x3005], 6);
+
+        assert!(machine.step_back());
+        assert!(machine.is_running());
+        assert_eq!(machine.registers.get_pc(), 0x3004);
+        assert!(machine.step_back());
+        assert!(machine.step_back());
+        assert_eq!(machine.memory[0x3005], 5);
+        assert_eq!(machine.registers.get_pc(), 0x3002);
+        assert_eq!(machine.registers.get_by_name(RegisterName::R1), 6);
+        assert!(machine.step_back());
+        assert!(machine.step_back());
+        assert_eq!(machine.registers.get_pc(), 0x3000);
+        assert_eq!(machine.registers.get_by_name(RegisterName::R1), 0);
+        assert!(!machine.step_back());
+
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(machine.memory[0x3005], 6);
+    }
+
+    #[test]
+    fn it_runs_backward_to_breakpoints_and_watched_writes() {
+        let mut machine = counter_machine();
+        machine.record_history(100);
+        assert_eq!(machine.run(), StopReason::Halted);
+        machine.add_watchpoint(0x3005..=0x3005, WatchKind::Write);
+        assert_eq!(
+            machine.run_backward(),
+            StopReason::Watchpoint {
+                address: 0x3005,
+                pc: 0x3003,
+                old: 6,
+                new: 6
+            }
+        );
+        assert_eq!(machine.registers.get_pc(), 0x3003);
+        machine.add_breakpoint(0x3001);
+        assert_eq!(
+            machine.run_backward(),
+            StopReason::Watchpoint {
+                address: 0x3005,
+                pc: 0x3002,
+                old: 5,
+                new: 6
+            }
+        );
+        assert_eq!(machine.run_backward(), StopReason::Breakpoint(0x3001));
+        assert_eq!(machine.run_backward(), StopReason::HistoryStart);
+        assert_eq!(machine.registers.get_pc(), 0x3000);
+    }
+
+    #[test]
+    fn it_resumes_identically_from_a_snapshot() {
+        let mut machine = counter_machine();
+        machine.write_memory(KBSR, 0x4000).unwrap();
+        assert_eq!(machine.run_for(3), StopReason::StepLimit);
+        let bytes = machine.snapshot().to_bytes();
+
+        let (mut restored, _console) = machine_with_console(b"");
+        restored
+            .restore(&Snapshot::from_bytes(&bytes).unwrap())
+            .unwrap();
+        assert_eq!(restored.get_memory(KBSR).unwrap(), 0x4000);
+        assert_eq!(restored.registers.get_pc(), 0x3003);
+        assert_eq!(restored.run(), StopReason::Halted);
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(
+            restored.snapshot().to_bytes(),
+            machine.snapshot().to_bytes()
+        );
+    }
+
+    #[test]
+    fn it_rejects_snapshots_of_other_devices() {
+        let mut machine = counter_machine();
+        let snapshot = machine.snapshot();
+        machine
+            .attach_device(0xFE10..=0xFE10, Box::new(Steps(0)))
+            .unwrap();
+        assert!(matches!(
+            machine.restore(&snapshot),
+            Err(SnapshotError::Incompatible(_))
+        ));
+    }
+
+    struct Records(Rc<RefCell<Vec<TraceRecord>>>);
+
+    impl TraceSink for Records {
+        fn record(&mut self, record: &TraceRecord) {
+            self.0.borrow_mut().push(record.clone());
+        }
+    }
+
+    #[test]
+    fn it_traces_executed_instructions() {
+        let mut machine = counter_machine();
+        let records = Rc::new(RefCell::new(Vec::new()));
+        machine.set_trace(Some(Box::new(Records(records.clone()))));
+        assert_eq!(machine.run(), StopReason::Halted);
+        let records = records.borrow();
+        assert_eq!(records.len(), 5);
+        assert_eq!(
+            records[1],
+            TraceRecord {
+                pc: 0x3001,
+                instruction: 0x1261,
+                registers: vec![(RegisterName::R1, 6)],
+                condition: 0b001,
+                writes: vec![],
+            }
+        );
+        assert_eq!(records[2].writes, vec![(0x3005, 6)]);
+        assert_eq!(records[4].pc, 0x3004);
+        assert!(records[4].writes.contains(&(MCR, 0)));
+    }
+
+    #[test]
+    fn it_stops_after_writes_to_watched_addresses() {
+        let mut machine = counter_machine();
+        machine.add_watchpoint(0x3005..=0x3005, WatchKind::Write);
+        let hit = |pc, old| StopReason::Watchpoint {
+            address: 0x3005,
+            pc,
+            old,
+            new: 6,
+        };
+        assert_eq!(machine.run(), hit(0x3002, 5));
+        assert_eq!(machine.registers.get_pc(), 0x3003);
+        assert_eq!(machine.run(), hit(0x3003, 6));
+        assert_eq!(machine.run(), StopReason::Halted);
+    }
+
+    #[test]
+    fn it_stops_after_reads_of_watched_ranges() {
+        let mut machine = counter_machine();
+        machine.add_watchpoint(0x3004..=0x3006, WatchKind::Read);
+        assert_eq!(
+            machine.run(),
+            StopReason::Watchpoint {
+                address: 0x3005,
+                pc: 0x3000,
+                old: 5,
+                new: 5
+            }
+        );
+        //fetching the HALT at x3004 does not count as a read
+        assert_eq!(machine.run(), StopReason::Halted);
+
+        let mut machine = counter_machine();
+        machine.add_watchpoint(0x3005..=0x3005, WatchKind::Access);
+        assert!(matches!(
+            machine.run(),
+            StopReason::Watchpoint { pc: 0x3000, .. }
+        ));
+        assert!(matches!(
+            machine.run(),
+            StopReason::Watchpoint { pc: 0x3002, .. }
+        ));
+        assert!(machine.remove_watchpoint(&(0x3005..=0x3005)));
+        assert!(!machine.remove_watchpoint(&(0x3005..=0x3005)));
+        assert_eq!(machine.run(), StopReason::Halted);
+    }
+
+    #[test]
+    fn it_polls_the_keyboard_registers() {
+        let (mut machine, console) = machine_with_console(b"");
+        assert_eq!(machine.get_memory(KBSR).unwrap(), 0);
+        console.push_input(b"ab");
+        assert_eq!(machine.get_memory(KBSR).unwrap(), 0x8000);
+        assert_eq!(machine.get_memory(KBSR).unwrap(), 0x8000);
+        assert_eq!(machine.get_memory(KBDR).unwrap(), b'a' as u16);
+        assert_eq!(machine.get_memory(KBDR).unwrap(), b'b' as u16);
+        assert_eq!(machine.get_memory(KBSR).unwrap(), 0);
+        assert_eq!(machine.get_memory(KBDR).unwrap(), b'b' as u16);
+    }
+
+    #[test]
+    fn it_echoes_through_the_device_registers() {
+        let program = crate::assembler::assemble(
+            ".ORIG x3000
+            POLL LDI R1, KBSR_ADDR
+                 BRzp POLL
+                 LDI R0, KBDR_ADDR
+            WAIT LDI R1, DSR_ADDR
+                 BRzp WAIT
+                 STI R0, DDR_ADDR
+                 HALT
+            KBSR_ADDR .FILL xFE00
+            KBDR_ADDR .FILL xFE02
+            DSR_ADDR  .FILL xFE04
+            DDR_ADDR  .FILL xFE06
+            .END",
+        )
+        .unwrap();
+        let (mut machine, console) = machine_with_console(b"q");
+        machine.load_object(&program.object);
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert!(console.output_string().starts_with("q\n--- halting"));
+    }
+
+    struct Steps(u16);
+
+    impl Device for Steps {
+        fn read(&mut self, _offset: u16, _console: &mut dyn Console) -> u16 {
+            self.0
+        }
+
+        fn write(&mut self, _offset: u16, _value: u16, _console: &mut dyn Console) {}
+
+        fn tick(&mut self, _console: &mut dyn Console) {
+            self.0 += 1;
+        }
+    }
+
+    #[test]
+    fn it_routes_the_io_page_to_attached_devices() {
+        let mut machine = Machine::empty().start();
+        machine
+            .attach_device(0xFE10..=0xFE10, Box::new(Steps(0)))
+            .unwrap();
+        assert!(machine
+            .attach_device(KBDR..=KBDR, Box::new(Steps(0)))
+            .is_err());
+        execute(&mut machine, 0x0000);
+        execute(&mut machine, 0x0000);
+        assert_eq!(machine.get_memory(0xFE10).unwrap(), 2);
+        //unmapped addresses in the I/O page fall through to memory
+        machine.write_memory(0xFE11, 42).unwrap();
+        assert_eq!(machine.get_memory(0xFE11).unwrap(), 42);
+    }
+
+    #[test]
+    fn it_takes_keyboard_interrupts_that_outrank_the_program() {
+        let (mut machine, console) = machine_with_console(b"");
+        machine.write_memory(0x0180, 0x1000).unwrap();
+        machine.registers.update_by_name(RegisterName::R6, 0x2000);
+        machine.write_memory(KBSR, 0x4000).unwrap();
+        execute(&mut machine, 0x0000);
+        assert_eq!(machine.registers.get_pc(), 0x301);
+
+        console.push_input(b"x");
+        machine.registers.set_priority(4);
+        execute(&mut machine, 0x0000);
+        assert_eq!(machine.registers.get_pc(), 0x302);
+
+        machine.registers.set_priority(3);
+        execute(&mut machine, 0x0000);
+        assert_eq!(machine.registers.get_pc(), 0x1000);
+        assert_eq!(machine.registers.priority(), 4);
+        assert_eq!(machine.get_memory(0x1FFF).unwrap(), 0x0302);
+        assert_eq!(machine.get_memory(0x1FFE).unwrap(), 0x303);
+    }
+
+    fn machine_with_os(input: &[u8], source: &str) -> (Machine, BufferConsole) {
+        let console = BufferConsole::new(input);
+        let mut machine = Machine::empty()
+            .with_console(Box::new(console.clone()))
+            .with_os()
+            .start();
+        let program = crate::assembler::assemble(source).unwrap();
+        machine.load_object(&program.object);
+        (machine, console)
+    }
+
+    #[test]
+    fn it_runs_traps_through_the_bundled_os() {
+        let (mut machine, console) = machine_with_os(
+            b"kz",
+            ".ORIG x3000
+                 LEA R0, HELLO
+                 PUTS
+                 LEA R0, PACKED
+                 PUTSP
+                 GETC
+                 OUT
+                 IN
+                 ADD R1, R0, #0
+                 .FILL xD000
+            HELLO .STRINGZ \"hi \"
+            PACKED .FILL x6261
+                   .FILL x0063
+            .END",
+        );
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(
+            console.output_string(),
+            "hi abck\nInput a character> z\n\n--- illegal opcode ---\n\n\n--- halting the LC-3 ---\n"
+        );
+        assert_eq!(machine.mcr(), MCR_FAULT);
+    }
+
+    #[test]
+    fn it_leaves_the_fault_bit_clear_on_an_os_halt() {
+        let (mut machine, _console) = machine_with_os(b"", ".ORIG x3000\nHALT\n.END");
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(machine.mcr(), 0);
+    }
+
+    #[test]
+    fn it_halts_again_when_resumed_after_an_os_halt() {
+        let (mut machine, console) = machine_with_os(b"", ".ORIG x3000\nHALT\n.END");
+        assert_eq!(machine.run(), StopReason::Halted);
+        machine.set_mcr(MCR_CLOCK_ENABLE);
+        assert!(machine.is_running());
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(console.output_string().matches("halting").count(), 2);
+    }
+
+    #[test]
+    fn it_stops_when_a_program_clears_the_clock_enable_bit() {
+        let mut machine = Machine::empty().start();
+        assert_eq!(machine.get_memory(MCR).unwrap(), MCR_CLOCK_ENABLE);
+        //AND R0, R0, #0; STI R0, #0 through a pointer to the MCR
+        machine.write_memory(0x300, 0x5020).unwrap();
+        machine.write_memory(0x301, 0xB000).unwrap();
+        machine.write_memory(0x302, MCR).unwrap();
+        assert_eq!(machine.run(), StopReason::Halted);
+        assert_eq!(machine.registers.get_pc(), 0x302);
+    }
+
+    #[test]
+    fn it_faults_on_the_reserved_opcode() {
+        let mut machine = Machine::empty().start();
+        machine.write_memory(0x300, 0xD000).unwrap();
+        let error = machine.step().unwrap_err();
+        assert_eq!(
+            error,
+            MachineError::ReservedOpcode {
+                pc: 0x300,
+                instruction: 0xD000
+            }
+        );
+        assert_eq!(machine.registers.get_pc(), 0x300);
+    }
+
+    #[test]
+    fn it_ignores_unused_bits_when_executing() {
+        let mut machine = Machine::empty().start();
+        //NOT R1, R2 with its low bits clear
+        machine.write_memory(0x300, 0x92BE).unwrap();
+        machine.registers.set_pc(0x300);
+        machine.registers.update_by_name(RegisterName::R2, 0x00FF);
+        assert_eq!(machine.step(), Ok(()));
+        assert_eq!(machine.registers.get_by_name(RegisterName::R1), 0xFF00);
+    }
+
+    #[test]
+    fn it_starts_in_supervisor_mode_at_priority_zero() {
+        let machine = Machine::empty().start();
+        assert_eq!(machine.registers().get_psr(), 0x0002);
+        assert!(!machine.registers().is_user_mode());
+    }
+
+    #[test]
+    fn it_switches_stacks_into_a_service_routine_and_back() {
+        let mut machine = Machine::empty().start();
+        machine.write_memory(0x0180, 0x1000).unwrap();
+        machine.write_memory(0x1000, 0x8000).unwrap();
+        machine.registers.set_pc(0x3000);
+        machine.registers.set_psr(0x8001);
+        machine.registers.update_by_name(RegisterName::R6, 0xF000);
+
+        machine.enter_service_routine(0x80, Some(4)).unwrap();
+        assert_eq!(machine.registers.get_pc(), 0x1000);
+        assert_eq!(machine.registers.get_psr(), 0x0401);
+        assert_eq!(machine.registers.get_by_name(RegisterName::R6), 0x2FFE);
+        assert_eq!(machine.registers.saved_usp(), 0xF000);
+        assert_eq!(machine.get_memory(0x2FFF).unwrap(), 0x8001);
+        assert_eq!(machine.get_memory(0x2FFE).unwrap(), 0x3000);
+
+        machine.step().unwrap();
+        assert_eq!(machine.registers.get_pc(), 0x3000);
+        assert_eq!(machine.registers.get_psr(), 0x8001);
+        assert_eq!(machine.registers.get_by_name(RegisterName::R6), 0xF000);
+        assert_eq!(machine.registers.saved_ssp(), 0x3000);
+    }
+
+    #[test]
+    fn it_stays_on_the_supervisor_stack_when_nested() {
+        let mut machine = Machine::empty().start();
+        machine.registers.set_psr(0x0302);
+        machine.registers.update_by_name(RegisterName::R6, 0x2F00);
+        machine.enter_service_routine(0x01, None).unwrap();
+        assert_eq!(machine.registers.get_by_name(RegisterName::R6), 0x2EFE);
+        assert_eq!(machine.registers.priority(), 3);
+        assert_eq!(machine.registers.saved_ssp(), 0x3000);
+    }
+
+    #[test]
+    fn it_faults_on_rti_in_user_mode() {
+        let mut machine = Machine::empty().start();
+        machine.write_memory(0x3000, 0x8000).unwrap();
+        machine.registers.set_pc(0x3000);
+        machine.registers.set_user_mode(true);
+        assert!(matches!(
+            machine.step(),
+            Err(MachineError::PrivilegeViolation { pc: 0x3000, .. })
+        ));
+    }
+
+    /// A machine in user mode at x3000 running `words`, with the exception
+    /// handlers for vectors x00-x02 at x1000, x1001 and x1002.
+    fn user_mode_machine_with_handlers(words: &[u16]) -> Machine {
+        let mut machine = Machine::empty().start();
+        for vector in 0..3 {
+            machine
+                .write_memory(VECTOR_TABLE | vector, 0x1000 | vector)
+                .unwrap();
+        }
+        for (offset, &word) in words.iter().enumerate() {
+            machine.write_memory(0x3000 + offset as u16, word).unwrap();
+        }
+        machine.registers.set_pc(0x3000);
+        machine.registers.set_psr(0x8002);
+        machine.registers.update_by_name(RegisterName::R6, 0xF000);
+        machine
+    }
+
+    #[test]
+    fn it_raises_exceptions_through_the_vector_table() {
+        //RTI, reserved opcode
+        for &(word, handler) in [(0x8000, 0x1000), (0xD000, 0x1001)].iter() {
+            let mut machine = user_mode_machine_with_handlers(&[word]);
+            machine.step().unwrap();
+            assert_eq!(machine.registers.get_pc(), handler);
+            assert!(!machine.registers.is_user_mode());
+            assert_eq!(machine.registers.get_by_name(RegisterName::R6), 0x2FFE);
+            assert_eq!(machine.get_memory(0x2FFF).unwrap(), 0x8002);
+            assert_eq!(machine.get_memory(0x2FFE).unwrap(), 0x3001);
+            assert_eq!(machine.registers.saved_usp(), 0xF000);
+        }
+    }
+
+    #[test]
+    fn it_raises_access_violations_for_user_mode_accesses_to_system_space() {
+        //LDI R0 through a pointer to x0200, and STR R0, R6, #0 into the I/O page
+        let mut machine = user_mode_machine_with_handlers(&[0xA000, 0x0200]);
+        machine.step().unwrap();
+        assert_eq!(machine.registers.get_pc(), 0x1002);
+
+        let mut machine = user_mode_machine_with_handlers(&[0x7180]);
+        machine.registers.update_by_name(RegisterName::R6, KBSR);
+        machine.step().unwrap();
+        assert_eq!(machine.registers.get_pc(), 0x1002);
+
+        //a fetch from system space saves the PC of the fetch
+        let mut machine = user_mode_machine_with_handlers(&[]);
+        machine.registers.set_pc(0x0400);
+        machine.step().unwrap();
+        assert_eq!(machine.registers.get_pc(), 0x1002);
+        assert_eq!(machine.get_memory(0x2FFE).unwrap(), 0x0400);
+    }
+
+    #[test]
+    fn it_reports_access_violations_without_a_handler() {
+        let mut machine = Machine::empty().start();
+        machine.write_memory(0x3000, 0xA000).unwrap();
+        machine.write_memory(0x3001, 0x0200).unwrap();
+        machine.registers.set_pc(0x3000);
+        machine.registers.set_user_mode(true);
+        assert_eq!(
+            machine.step(),
+            Err(MachineError::AccessViolation {
+                pc: 0x3000,
+                instruction: 0xA000,
+                address: 0x0200
+            })
+        );
+        assert_eq!(machine.registers.get_pc(), 0x3000);
+    }
+
+    #[test]
+    fn it_lets_built_in_traps_read_system_space_from_user_mode() {
+        let (mut machine, console) = machine_with_console(b"");
+        machine.write_memory(0x0200, b'!' as u16).unwrap();
+        machine.write_memory(0x3000, 0xF022).unwrap();
+        machine.registers.set_pc(0x3000);
+        machine.registers.update_by_name(RegisterName::R0, 0x0200);
+        machine.registers.set_user_mode(true);
+        machine.step().unwrap();
+        assert_eq!(console.output_string(), "!");
+    }
+}
+
+/// Conformance tests for each opcode, written against the instruction encodings
+/// in the LC-3 ISA specification rather than the instruction builder.
+#[cfg(test)]
+mod isa_tests {
+    use super::*;
+    use crate::registers::ConditionFlag;
+
+    const N: u16 = ConditionFlag::Negative as u16;
+    const Z: u16 = ConditionFlag::Zero as u16;
+    const P: u16 = ConditionFlag::Positive as u16;
+
+    fn machine_with_program(origin: u16, words: &[u16]) -> Machine {
+        let mut machine = Machine::empty().start();
+        machine.load_object(&ObjectFile {
+            origin,
+            words: words.to_vec(),
+        });
+        machine
+    }
+
+    fn program(words: &[u16]) -> Machine {
+        machine_with_program(0x3000, words)
+    }
+
+    fn set(machine: &mut Machine, register: RegisterName, value: u16) {
+        machine.registers.update_by_name(register, value);
+    }
+
+    fn get(machine: &Machine, register: RegisterName) -> u16 {
+        machine.registers.get_by_name(register)
+    }
+
+    fn cc(machine: &Machine) -> u16 {
+        machine.registers.get_cond_flag()
+    }
+
+    #[test]
+    fn it_starts_with_the_zero_condition_code() {
+        let machine = Machine::empty();
+        assert_eq!(cc(&machine), Z);
+    }
+
+    #[test]
+    fn add_register_mode() {
+        let mut machine = program(&[0x1642]); //ADD R3, R1, R2
+        set(&mut machine, RegisterName::R1, 5);
+        set(&mut machine, RegisterName::R2, -7i16 as u16);
+        machine.step().unwrap();
+        assert_eq!(get(&machine, RegisterName::R3), -2i16 as u16);
+        assert_eq!(cc(&machine), N);
+        assert_eq!(machine.registers.get_pc(), 0x3001);
+    }
+
+    #[test]
+    fn add_immediate_mode_sets_zero() {
+        let mut machine = program(&[0x103F]); //ADD R0, R0, #-1
+        set(&mut machine, RegisterName::R0, 1);
+        machine.step().unwrap();
+        assert_eq!(get(&machine, RegisterName::R0), 0);
+        assert_eq!(cc(&machine), Z);
+    }
+
+    #[test]
+    fn add_immediate_mode_sets_positive() {
+        let mut machine = program(&[0x192F]); //ADD R4, R4, #15
+        machine.step().unwrap();
+        assert_eq!(get(&machine, RegisterName::R4), 15);
+        assert_eq!(cc(&machine), P);
+    }
+
+    #[test]
+    fn add_wraps_on_overflow() {
+        let mut machine = program(&[0x1261]); //ADD R1, R1, #1
+        set(&mut machine, RegisterName::R1, 0x7FFF);
+        machine.step().unwrap();
+        assert_eq!(get(&machine, RegisterName::R1), 0x8000);
+        assert_eq!(cc(&machine), N);
+    }
+
+    #[test]
+    fn and_register_mode() {
+        let mut machine = program(&[0x5443]); //AND R2, R1, R3
+        set(&mut machine, RegisterName::R1, 0x00FF);
+        set(&mut machine, RegisterName::R3, 0x0F0F);
+        machine.step().unwrap();
+        assert_eq!(get(&machine, RegisterName::R2), 0x000F);
+        assert_eq!(cc(&machine), P);
+    }
+
+    #[test]
+    fn and_immediate_clears_a_register() {
+        let mut machine = program(&[0x5B60]); //AND R5, R5, #0
+        set(&mut machine, RegisterName::R5, 0x1234);
+        machine.step().unwrap();
+        assert_eq!(get(&machine, RegisterName::R5), 0);
+        assert_eq!(cc(&machine), Z);
+    }
+
+    #[test]
+    fn and_immediate_is_sign_extended() {
+        let mut machine = program(&[0x527E]); //AND R1, R1, #-2
+        set(&mut machine, RegisterName::R1, 0x8003);
+        machine.step().unwrap();
+        assert_eq!(get(&machine, RegisterName::R1), 0x8002);
+        assert_eq!(cc(&machine), N);
+    }
+
+    #[test]
+    fn not() {
+        let mut machine = program(&[0x92BF]); //NOT R1, R2
+        machine.step().unwrap();
+        assert_eq!(get(&machine, RegisterName::R1), 0xFFFF);
+        assert_eq!(cc(&machine), N);
+    }
+
+    #[test]
+    fn branch_on_every_condition_combination() {
+        let values = [(N, 0x8000), (Z, 0), (P, 1)];
+        for nzp in 0..8u16 {
+            for &(flag, value) in values.iter() {
+                let mut machine = program(&[nzp << 9 | 5]); //BR<nzp> #5
+                machine
+                    .registers
+                    .update_by_name_set_condition_flag(RegisterName::R0, value);
+                machine.step().unwrap();
+                let expected = if nzp & flag != 0 { 0x3006 } else { 0x3001 };
+                assert_eq!(machine.registers.get_pc(), expected, "nzp={:03b}", nzp);
+                assert_eq!(cc(&machine), flag);
+            }
+        }
+    }
+
+    #[test]
+    fn branch_backwards() {
+        let mut machine = program(&[0x0FFE]); //BRnzp #-2
+        machine.step().unwrap();
+        assert_eq!(machine.registers.get_pc(), 0x2FFF);
+    }
+
+    #[test]
+    fn branch_wraps_around_the_address_space() {
+        let mut machine = machine_with_program(0x0000, &[0x0FFE]); //BRnzp #-2
+        machine.step().unwrap();
+        assert_eq!(machine.registers.get_pc(), 0xFFFF);
+    }
+
+    #[test]
+    fn load() {
+        let mut machine = program(&[0x2403]); //LD R2, #3
+        machine.write_memory(0x3004, 0x8000).unwrap();
+        machine.step().unwrap();
+        assert_eq!(get(&machine, RegisterName::R2), 0x8000);
+        assert_eq!(cc(&machine), N);
+    }
+
+    #[test]
+    fn load_with_negative_offset() {
+        let mut machine = program(&[0x21FF]); //LD R0, #-1
+        machine.step().unwrap();
+        assert_eq!(get(&machine, RegisterName::R0), 0x21FF);
+        assert_eq!(cc(&machine), P);
+    }
+
+    #[test]
+    fn load_indirect() {
+        let mut machine = program(&[0xA601, 0, 0x4000]); //LDI R3, #1
+        machine.write_memory(0x4000, 0).unwrap();
+        set(&mut machine, RegisterName::R3, 0x1234);
+        machine.step().unwrap();
+        assert_eq!(get(&machine, RegisterName::R3), 0);
+        assert_eq!(cc(&machine), Z);
+    }
+
+    #[test]
+    fn load_register_with_negative_offset() {
+        let mut machine = program(&[0x687D]); //LDR R4, R1, #-3
+        set(&mut machine, RegisterName::R1, 0x4003);
+        machine.write_memory(0x4000, 0x1234).unwrap();
+        machine.step().unwrap();
+        assert_eq!(get(&machine, RegisterName::R4), 0x1234);
+        assert_eq!(cc(&machine), P);
+    }
+
+    #[test]
+    fn load_effective_address_does_not_read_memory_or_set_condition_codes() {
+        let mut machine = program(&[0xEBF0]); //LEA R5, #-16
+        machine.write_memory(0x2FF1, 0x9999).unwrap();
+        machine
+            .registers
+            .update_by_name_set_condition_flag(RegisterName::R1, 0);
+        machine.step().unwrap();
+        assert_eq!(get(&machine, RegisterName::R5), 0x2FF1);
+        assert_eq!(cc(&machine), Z);
+        assert_eq!(machine.registers.get_pc(), 0x3001);
+    }
+
+    #[test]
+    fn store() {
+        let mut machine = program(&[0x3C02]); //ST R6, #2
+        set(&mut machine, RegisterName::R6, 0xABCD);
+        machine.step().unwrap();
+        assert_eq!(machine.get_memory(0x3003).unwrap(), 0xABCD);
+    }
+
+    #[test]
+    fn store_indirect() {
+        let mut machine = program(&[0xBE01, 0, 0x4000]); //STI R7, #1
+        set(&mut machine, RegisterName::R7, 0x55);
+        machine.step().unwrap();
+        assert_eq!(machine.get_memory(0x4000).unwrap(), 0x55);
+        assert_eq!(machine.get_memory(0x3002).unwrap(), 0x4000);
+    }
+
+    #[test]
+    fn store_register_with_negative_offset() {
+        let mut machine = program(&[0x71A0]); //STR R0, R6, #-32
+        set(&mut machine, RegisterName::R6, 0x4020);
+        set(&mut machine, RegisterName::R0, 7);
+        machine.step().unwrap();
+        assert_eq!(machine.get_memory(0x4000).unwrap(), 7);
+    }
+
+    #[test]
+    fn jump() {
+        let mut machine = program(&[0xC080]); //JMP R2
+        set(&mut machine, RegisterName::R2, 0x4567);
+        machine.step().unwrap();
+        assert_eq!(machine.registers.get_pc(), 0x4567);
+    }
+
+    #[test]
+    fn ret() {
+        let mut machine = program(&[0xC1C0]); //RET
+        set(&mut machine, RegisterName::R7, 0x3456);
+        machine.step().unwrap();
+        assert_eq!(machine.registers.get_pc(), 0x3456);
+    }
+
+    #[test]
+    fn jump_subroutine_with_offset() {
+        let mut machine = program(&[0x4C00]); //JSR #-1024
+        machine.step().unwrap();
+        assert_eq!(get(&machine, RegisterName::R7), 0x3001);
+        assert_eq!(machine.registers.get_pc(), 0x2C01);
+    }
+
+    #[test]
+    fn jump_subroutine_register() {
+        let mut machine = program(&[0x40C0]); //JSRR R3
+        set(&mut machine, RegisterName::R3, 0x5000);
+        machine.step().unwrap();
+        assert_eq!(get(&machine, RegisterName::R7), 0x3001);
+        assert_eq!(machine.registers.get_pc(), 0x5000);
+    }
+
+    #[test]
+    fn jump_subroutine_register_through_r7() {
+        let mut machine = program(&[0x41C0]); //JSRR R7
+        set(&mut machine, RegisterName::R7, 0x5000);
+        machine.step().unwrap();
+        assert_eq!(get(&machine, RegisterName::R7), 0x3001);
+        assert_eq!(machine.registers.get_pc(), 0x5000);
+    }
+
+    #[test]
+    fn trap_through_the_vector_table() {
+        let mut machine = program(&[0xF040]); //TRAP x40
+        machine.write_memory(0x0040, 0x1000).unwrap();
+        machine.step().unwrap();
+        assert_eq!(get(&machine, RegisterName::R7), 0x3001);
+        assert_eq!(machine.registers.get_pc(), 0x1000);
+    }
+
+    #[test]
+    fn control_flow_and_stores_leave_condition_codes_alone() {
+        let instructions = [
+            0x0E00, //BRnzp #0
+            0x3C02, //ST R6, #2
+            0xBE01, //STI R7, #1
+            0x71A0, //STR R0, R6, #-32
+            0xC080, //JMP R2
+            0x4C00, //JSR #-1024
+            0x40C0, //JSRR R3
+            0xF040, //TRAP x40
+        ];
+        for &instruction in instructions.iter() {
+            let mut machine = program(&[instruction, 0, 0x4000]);
+            machine
+                .registers
+                .update_by_name_set_condition_flag(RegisterName::R1, 0x8000);
+            machine.step().unwrap();
+            assert_eq!(cc(&machine), N, "instruction x{:04X}", instruction);
+        }
+    }
 }
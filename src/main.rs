@@ -1,15 +1,220 @@
-use machine::Machine;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::TcpListener;
+use std::path::Path;
+use std::process;
 
-extern crate num;
-#[macro_use]
-extern crate num_derive;
-mod instruction_builder;
-mod machine;
-mod opcodes;
-mod registers;
-mod util;
+#[cfg(unix)]
+use lc3::console::RawMode;
+use lc3::console::{Console, StdConsole};
+use lc3::debugger::Debugger;
+use lc3::gdb;
+use lc3::history::History;
+use lc3::loader::ObjectFile;
+use lc3::machine::{Machine, StopReason, MCR_FAULT};
+use lc3::os;
+use lc3::snapshot::Snapshot;
+use lc3::symbols::SymbolTable;
+use lc3::trace::{BinaryTrace, TextTrace, TraceError, TraceReader, TraceSink};
+use lc3::tracediff::{self, Comparison};
+
+use cli::{Command, RunOptions};
+
+mod cli;
 
 fn main() {
-    let mut machine = Machine::empty().start();
-    machine.step();
+    let args: Vec<String> = env::args().skip(1).collect();
+    match cli::parse(&args) {
+        Ok(Command::Help) => print!("{}", cli::USAGE),
+        Ok(Command::Run(options)) => run(&options),
+        Ok(Command::Debug(options)) => debug(&options),
+        Ok(Command::Gdb { options, port }) => serve_gdb(&options, port),
+        Ok(Command::DiffTrace { ours, theirs }) => diff_trace(&ours, &theirs),
+        Err(message) => {
+            eprint!("lc3: {}\n\n{}", message, cli::USAGE);
+            process::exit(2);
+        }
+    }
+}
+
+/// Builds a machine with every image loaded and the PC at the start address.
+fn load(options: &RunOptions) -> Machine {
+    let mut machine = Machine::empty();
+    if options.os {
+        machine = machine.with_os();
+    }
+    let mut machine = machine.start();
+    let mut start = None;
+    if let Some(path) = &options.snapshot {
+        if let Err(error) = Snapshot::read(path).and_then(|snapshot| machine.restore(&snapshot)) {
+            eprintln!("lc3: {}: {}", path, error);
+            process::exit(1);
+        }
+        //resume where the snapshot was taken
+        start = Some(machine.registers().get_pc());
+    }
+    for path in &options.files {
+        match ObjectFile::read(path) {
+            Ok(object) => {
+                machine.load_object(&object);
+                start.get_or_insert(object.origin);
+            }
+            Err(error) => {
+                eprintln!("lc3: {}: {}", path, error);
+                process::exit(1);
+            }
+        }
+    }
+    if let Some(pc) = options.pc.or(start) {
+        machine.registers_mut().set_pc(pc);
+    }
+    if let Some(path) = &options.trace {
+        let file = match File::create(path) {
+            Ok(file) => BufWriter::new(file),
+            Err(error) => {
+                eprintln!("lc3: {}: {}", path, error);
+                process::exit(1);
+            }
+        };
+        let sink: Box<dyn TraceSink> = if options.binary_trace {
+            Box::new(BinaryTrace::new(file))
+        } else {
+            Box::new(TextTrace::new(file))
+        };
+        machine.set_trace(Some(sink));
+    }
+    machine
+}
+
+/// Flushes the trace, if there is one, reporting any error writing it.
+fn finish_trace(machine: &mut Machine) {
+    if let Some(mut trace) = machine.set_trace(None) {
+        if let Err(error) = trace.flush() {
+            eprintln!("lc3: trace: {}", error);
+            process::exit(1);
+        }
+    }
+}
+
+fn run(options: &RunOptions) {
+    let mut machine = load(options);
+    #[cfg(unix)]
+    let raw_mode = RawMode::enable();
+    let stop = machine.run();
+    #[cfg(unix)]
+    drop(raw_mode);
+    finish_trace(&mut machine);
+    match stop {
+        StopReason::Fault(error) => {
+            eprintln!("lc3: {}", error);
+            process::exit(1);
+        }
+        //the OS has already reported the exception
+        StopReason::Halted if machine.mcr() & MCR_FAULT != 0 => process::exit(1),
+        _ => (),
+    }
+}
+
+/// The symbols of every loaded image, from the `.sym` file beside each object.
+fn symbols(options: &RunOptions) -> SymbolTable {
+    let mut symbols = SymbolTable::new();
+    if options.os {
+        for (label, address) in os::program().symbols.iter() {
+            symbols.insert(label, address);
+        }
+    }
+    for path in &options.files {
+        if let Ok(source) = fs::read_to_string(Path::new(path).with_extension("sym")) {
+            for (label, address) in SymbolTable::parse(&source).iter() {
+                symbols.insert(label, address);
+            }
+        }
+    }
+    symbols
+}
+
+/// Reads a line a byte at a time, so that no input meant for the program is
+/// buffered away from it. Returns `None` at end of input.
+fn read_line(console: &mut StdConsole) -> Option<String> {
+    let mut line = Vec::new();
+    loop {
+        match console.read_char() {
+            Some(b'\n') => break,
+            Some(c) => line.push(c),
+            None if line.is_empty() => return None,
+            None => break,
+        }
+    }
+    Some(String::from_utf8_lossy(&line).trim().to_string())
+}
+
+fn debug(options: &RunOptions) {
+    let mut debugger = Debugger::new(load(options), symbols(options));
+    let mut console = StdConsole;
+    let mut last = String::new();
+    loop {
+        print!("(lc3) ");
+        io::stdout().flush().unwrap();
+        let line = match read_line(&mut console) {
+            Some(line) => line,
+            None => break,
+        };
+        //an empty line repeats the last command
+        if !line.is_empty() {
+            last = line;
+        }
+        match last.as_str() {
+            "quit" | "q" => break,
+            command => match debugger.execute(command) {
+                Ok(output) => print!("{}", output),
+                Err(message) => println!("{}", message),
+            },
+        }
+    }
+    finish_trace(debugger.machine_mut());
+}
+
+fn serve_gdb(options: &RunOptions, port: u16) {
+    let mut machine = load(options);
+    //so that clients can reverse step and continue
+    machine.record_history(History::DEFAULT_LIMIT);
+    let result = TcpListener::bind(("127.0.0.1", port)).and_then(|listener| {
+        eprintln!("lc3: waiting for gdb on 127.0.0.1:{}", port);
+        let (stream, _) = listener.accept()?;
+        gdb::serve(&mut machine, stream)
+    });
+    if let Err(error) = result {
+        eprintln!("lc3: {}", error);
+        process::exit(1);
+    }
+    finish_trace(&mut machine);
+}
+
+fn open_trace(path: &str) -> TraceReader<BufReader<File>> {
+    let reader = File::open(path)
+        .map_err(TraceError::from)
+        .and_then(|file| TraceReader::new(BufReader::new(file)));
+    match reader {
+        Ok(reader) => reader,
+        Err(error) => {
+            eprintln!("lc3: {}: {}", path, error);
+            process::exit(2);
+        }
+    }
+}
+
+/// Exits with 1 if the traces diverge, like `diff`.
+fn diff_trace(ours: &str, theirs: &str) {
+    match tracediff::compare(open_trace(ours), open_trace(theirs)) {
+        Ok(Comparison::Match { count, .. }) => println!("traces match ({} instructions)", count),
+        Ok(Comparison::Diverged(divergence)) => {
+            print!("{}", divergence);
+            process::exit(1);
+        }
+        Err(error) => {
+            eprintln!("lc3: {}", error);
+            process::exit(2);
+        }
+    }
 }
@@ -0,0 +1,34 @@
+use std::sync::OnceLock;
+
+use crate::assembler::{assemble, Program};
+
+/// The source of the bundled operating system. See `os/lc3os.asm`.
+pub const SOURCE: &str = include_str!("../os/lc3os.asm");
+
+/// The bundled operating system, assembled from [`SOURCE`] on first use.
+pub fn program() -> &'static Program {
+    static PROGRAM: OnceLock<Program> = OnceLock::new();
+    PROGRAM.get_or_init(|| match assemble(SOURCE) {
+        Ok(program) => program,
+        Err(error) => panic!("the bundled OS does not assemble:\n{}", error),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_fills_system_space_with_the_vector_tables_and_routines() {
+        let program = program();
+        let words = &program.object.words;
+        let symbols = &program.symbols;
+        assert_eq!(program.object.origin, 0x0000);
+        assert_eq!(symbols.get("OS_START"), Some(0x0200));
+        assert_eq!(Some(words[0x25]), symbols.get("TRAP_HALT"));
+        assert_eq!(Some(words[0x26]), symbols.get("BAD_TRAP"));
+        assert_eq!(Some(words[0x101]), symbols.get("EXC_ILLEGAL"));
+        assert_eq!(Some(words[0x180]), symbols.get("BAD_INT"));
+        assert!(words.len() < 0x3000);
+    }
+}
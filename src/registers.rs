@@ -1,8 +1,16 @@
 extern crate num;
+pub const PSR_USER_MODE: u16 = 1 << 15;
+pub const PSR_PRIORITY: u16 = 0b111 << 8;
+/// The supervisor stack grows down from the start of user space.
+pub const INITIAL_SSP: u16 = 0x3000;
 #[derive(Clone, Debug)]
 pub struct Registers {
     pc: u16,
     cond: u16,
+    //the privilege and priority bits of the PSR; the condition codes live in `cond`
+    psr: u16,
+    saved_ssp: u16,
+    saved_usp: u16,
     r0: u16,
     r1: u16,
     r2: u16,
@@ -12,24 +20,45 @@ pub struct Registers {
     r6: u16,
     r7: u16,
 }
-#[derive(Clone, Copy, FromPrimitive)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, FromPrimitive)]
 pub enum RegisterName {
-    Pc = 0,
-    Cond = 1,
-    R0 = 2,
-    R1 = 3,
-    R2 = 4,
-    R3 = 5,
-    R4 = 6,
-    R5 = 7,
-    R6 = 8,
-    R7 = 9,
+    //general purpose registers are numbered as they are encoded in instructions
+    R0 = 0,
+    R1 = 1,
+    R2 = 2,
+    R3 = 3,
+    R4 = 4,
+    R5 = 5,
+    R6 = 6,
+    R7 = 7,
+    Pc = 8,
+    Cond = 9,
+    Psr = 10,
 }
+impl RegisterName {
+    /// The general purpose register named by the low three bits of an instruction field.
+    pub fn from_field(field: u16) -> RegisterName {
+        match field & 0b111 {
+            0 => RegisterName::R0,
+            1 => RegisterName::R1,
+            2 => RegisterName::R2,
+            3 => RegisterName::R3,
+            4 => RegisterName::R4,
+            5 => RegisterName::R5,
+            6 => RegisterName::R6,
+            _ => RegisterName::R7,
+        }
+    }
+}
+
 impl Registers {
     pub fn new() -> Registers {
         Registers {
             pc: 0,
-            cond: 0,
+            cond: ConditionFlag::Zero as u16,
+            psr: 0,
+            saved_ssp: INITIAL_SSP,
+            saved_usp: 0,
             r0: 0,
             r1: 0,
             r2: 0,
@@ -63,6 +92,7 @@ impl Registers {
             RegisterName::R6 => self.r6 = register_value,
             RegisterName::R7 => self.r7 = register_value,
             RegisterName::Cond => panic!("Cond register should not be set directly"),
+            RegisterName::Psr => self.set_psr(register_value),
         }
     }
 
@@ -71,33 +101,25 @@ impl Registers {
         register_name: u16,
         register_value: u16,
     ) {
-        let register = num::FromPrimitive::from_u16(register_name);
-        match register {
-            Some(value) => self.update_by_name_set_condition_flag(value, register_value),
-            None => panic!("unrecognized register"),
-        }
+        self.update_by_name_set_condition_flag(
+            RegisterName::from_field(register_name),
+            register_value,
+        );
     }
 
     #[cfg(test)]
     pub fn update_by_address(&mut self, register_name: u16, register_value: u16) {
-        let register = num::FromPrimitive::from_u16(register_name);
-        match register {
-            Some(value) => self.update_by_name(value, register_value),
-            None => panic!("unrecognized register"),
-        }
+        self.update_by_name(RegisterName::from_field(register_name), register_value);
     }
 
     pub fn get_by_address(&self, register_name: u16) -> u16 {
-        let register = num::FromPrimitive::from_u16(register_name);
-        match register {
-            Some(value) => self.get_by_name(value),
-            None => panic!("unrecognized register"),
-        }
+        self.get_by_name(RegisterName::from_field(register_name))
     }
     pub fn get_by_name(&self, register_name: RegisterName) -> u16 {
         match register_name {
             RegisterName::Pc => self.pc,
             RegisterName::Cond => self.cond,
+            RegisterName::Psr => self.get_psr(),
             RegisterName::R0 => self.r0,
             RegisterName::R1 => self.r1,
             RegisterName::R2 => self.r2,
@@ -124,7 +146,7 @@ impl Registers {
     }
 
     pub fn increment_pc(&mut self) {
-        self.pc += 1;
+        self.pc = self.pc.wrapping_add(1);
     }
 
     pub fn set_pc(&mut self, pc: u16) {
@@ -134,6 +156,61 @@ impl Registers {
     pub fn get_pc(&self) -> u16 {
         self.pc
     }
+
+    /// The processor status register: privilege in bit 15 (1 is user mode),
+    /// priority in bits 10-8 and the condition codes in bits 2-0.
+    pub fn get_psr(&self) -> u16 {
+        self.psr | self.cond
+    }
+
+    pub fn set_psr(&mut self, psr: u16) {
+        self.psr = psr & (PSR_USER_MODE | PSR_PRIORITY);
+        self.cond = psr & 0b111;
+    }
+
+    pub fn is_user_mode(&self) -> bool {
+        self.psr & PSR_USER_MODE != 0
+    }
+
+    pub fn set_user_mode(&mut self, user_mode: bool) {
+        if user_mode {
+            self.psr |= PSR_USER_MODE;
+        } else {
+            self.psr &= !PSR_USER_MODE;
+        }
+    }
+
+    pub fn priority(&self) -> u16 {
+        (self.psr & PSR_PRIORITY) >> 8
+    }
+
+    pub fn set_priority(&mut self, priority: u16) {
+        self.psr = (self.psr & !PSR_PRIORITY) | (priority & 0b111) << 8;
+    }
+
+    /// The supervisor stack pointer, while R6 holds the user stack pointer.
+    pub fn saved_ssp(&self) -> u16 {
+        self.saved_ssp
+    }
+
+    pub fn set_saved_ssp(&mut self, ssp: u16) {
+        self.saved_ssp = ssp;
+    }
+
+    /// The user stack pointer, while R6 holds the supervisor stack pointer.
+    pub fn saved_usp(&self) -> u16 {
+        self.saved_usp
+    }
+
+    pub fn set_saved_usp(&mut self, usp: u16) {
+        self.saved_usp = usp;
+    }
+}
+
+impl Default for Registers {
+    fn default() -> Registers {
+        Registers::new()
+    }
 }
 
 pub enum ConditionFlag {
@@ -0,0 +1,223 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::registers::{RegisterName, Registers};
+
+const MAGIC: &[u8; 4] = b"LC3S";
+pub const VERSION: u16 = 1;
+
+/// The full state of a machine: registers, the MCR, memory and the state of
+/// each mapped device, in mapping order.
+///
+/// The file format is the magic bytes `LC3S` followed by big-endian words: the
+/// version, R0-R7, PC, PSR, the saved SSP and USP, the MCR, whether host trap
+/// routines are in use, the memory size as a high and a low word (a full
+/// memory does not fit in one) and the contents, then the device count
+/// and each device's state as a length and that many words.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    pub registers: Registers,
+    pub mcr: u16,
+    pub host_traps: bool,
+    pub memory: Vec<u16>,
+    pub devices: Vec<Vec<u16>>,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    /// The file does not start with the snapshot magic bytes.
+    NotASnapshot,
+    UnsupportedVersion(u16),
+    /// The file ends before the state it describes.
+    Truncated,
+    /// The snapshot's memory size or devices differ from the machine's.
+    Incompatible(String),
+}
+
+const GENERAL_PURPOSE: [RegisterName; 8] = [
+    RegisterName::R0,
+    RegisterName::R1,
+    RegisterName::R2,
+    RegisterName::R3,
+    RegisterName::R4,
+    RegisterName::R5,
+    RegisterName::R6,
+    RegisterName::R7,
+];
+
+impl Snapshot {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot, SnapshotError> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(SnapshotError::NotASnapshot);
+        }
+        let mut words = bytes[MAGIC.len()..]
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]));
+        let mut next = || words.next().ok_or(SnapshotError::Truncated);
+        let version = next()?;
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        let mut registers = Registers::new();
+        for &register in GENERAL_PURPOSE.iter() {
+            registers.update_by_name(register, next()?);
+        }
+        registers.set_pc(next()?);
+        registers.set_psr(next()?);
+        registers.set_saved_ssp(next()?);
+        registers.set_saved_usp(next()?);
+        let mcr = next()?;
+        let host_traps = next()? != 0;
+        let memory_size = (next()? as u32) << 16 | next()? as u32;
+        let memory = (0..memory_size).map(|_| next()).collect::<Result<_, _>>()?;
+        let device_count = next()?;
+        let mut devices = Vec::new();
+        for _ in 0..device_count {
+            let length = next()?;
+            devices.push((0..length).map(|_| next()).collect::<Result<_, _>>()?);
+        }
+        Ok(Snapshot {
+            registers,
+            mcr,
+            host_traps,
+            memory,
+            devices,
+        })
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Snapshot, SnapshotError> {
+        let bytes = fs::read(path)?;
+        Snapshot::from_bytes(&bytes)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let registers = &self.registers;
+        let mut words = vec![VERSION];
+        words.extend(
+            GENERAL_PURPOSE
+                .iter()
+                .map(|&register| registers.get_by_name(register)),
+        );
+        words.extend_from_slice(&[
+            registers.get_pc(),
+            registers.get_psr(),
+            registers.saved_ssp(),
+            registers.saved_usp(),
+            self.mcr,
+            self.host_traps as u16,
+            (self.memory.len() >> 16) as u16,
+            self.memory.len() as u16,
+        ]);
+        words.extend_from_slice(&self.memory);
+        words.push(self.devices.len() as u16);
+        for state in &self.devices {
+            words.push(state.len() as u16);
+            words.extend_from_slice(state);
+        }
+        let mut bytes = MAGIC.to_vec();
+        for word in words {
+            bytes.extend_from_slice(&word.to_be_bytes());
+        }
+        bytes
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(error) => write!(f, "{}", error),
+            SnapshotError::NotASnapshot => write!(f, "not a machine snapshot"),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot version {}", version)
+            }
+            SnapshotError::Truncated => write!(f, "snapshot is truncated"),
+            SnapshotError::Incompatible(reason) => {
+                write!(f, "snapshot does not fit this machine: {}", reason)
+            }
+        }
+    }
+}
+
+impl Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SnapshotError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(error: io::Error) -> SnapshotError {
+        SnapshotError::Io(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        let mut registers = Registers::new();
+        registers.update_by_name(RegisterName::R3, 0xBEEF);
+        registers.set_pc(0x3001);
+        registers.set_psr(0x8402);
+        registers.set_saved_ssp(0x2FF0);
+        Snapshot {
+            registers,
+            mcr: 0x8000,
+            host_traps: true,
+            memory: vec![1, 2, 3],
+            devices: vec![vec![], vec![0x61, 1, 0]],
+        }
+    }
+
+    #[test]
+    fn it_round_trips_through_bytes() {
+        let bytes = snapshot().to_bytes();
+        assert!(bytes.starts_with(b"LC3S\x00\x01"));
+        let restored = Snapshot::from_bytes(&bytes).unwrap();
+        assert_eq!(restored.registers.get_by_name(RegisterName::R3), 0xBEEF);
+        assert_eq!(restored.registers.get_psr(), 0x8402);
+        assert_eq!(restored.registers.saved_ssp(), 0x2FF0);
+        assert_eq!(restored.memory, vec![1, 2, 3]);
+        assert_eq!(restored.devices, vec![vec![], vec![0x61, 1, 0]]);
+        assert_eq!(restored.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn it_round_trips_a_full_memory() {
+        let mut full = snapshot();
+        full.memory = vec![0; 1 << 16];
+        full.memory[0xFFFF] = 7;
+        let restored = Snapshot::from_bytes(&full.to_bytes()).unwrap();
+        assert_eq!(restored.memory.len(), 1 << 16);
+        assert_eq!(restored.memory[0xFFFF], 7);
+    }
+
+    #[test]
+    fn it_rejects_other_files() {
+        let mut bytes = snapshot().to_bytes();
+        assert!(matches!(
+            Snapshot::from_bytes(&bytes[..bytes.len() - 2]),
+            Err(SnapshotError::Truncated)
+        ));
+        bytes[5] = 2;
+        assert!(matches!(
+            Snapshot::from_bytes(&bytes),
+            Err(SnapshotError::UnsupportedVersion(2))
+        ));
+        assert!(matches!(
+            Snapshot::from_bytes(&[0x30, 0x00]),
+            Err(SnapshotError::NotASnapshot)
+        ));
+    }
+}
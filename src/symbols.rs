@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Label addresses, as written to and read from `.sym` files.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SymbolTable {
+    symbols: BTreeMap<String, u16>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable::default()
+    }
+
+    /// Returns false, leaving the table unchanged, if the label is already defined.
+    pub fn insert(&mut self, label: &str, address: u16) -> bool {
+        if self.symbols.contains_key(label) {
+            return false;
+        }
+        self.symbols.insert(label.to_string(), address);
+        true
+    }
+
+    pub fn get(&self, label: &str) -> Option<u16> {
+        self.symbols.get(label).copied()
+    }
+
+    /// The first label, alphabetically, defined at `address`.
+    pub fn label_at(&self, address: u16) -> Option<&str> {
+        self.symbols
+            .iter()
+            .find(|(_, &value)| value == address)
+            .map(|(label, _)| label.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// Symbols ordered by address, then by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u16)> {
+        let mut entries: Vec<(&str, u16)> = self
+            .symbols
+            .iter()
+            .map(|(label, &address)| (label.as_str(), address))
+            .collect();
+        entries.sort_by_key(|&(_, address)| address);
+        entries.into_iter()
+    }
+
+    /// Parses the `.sym` format produced by the reference assembler, skipping
+    /// the header and any line that is not a `label address` pair.
+    pub fn parse(source: &str) -> SymbolTable {
+        let mut table = SymbolTable::new();
+        for line in source.lines() {
+            let line = line.trim_start_matches("//");
+            let mut fields = line.split_whitespace();
+            if let (Some(label), Some(address), None) =
+                (fields.next(), fields.next(), fields.next())
+            {
+                if let Ok(address) = u16::from_str_radix(address, 16) {
+                    table.insert(label, address);
+                }
+            }
+        }
+        table
+    }
+}
+
+impl fmt::Display for SymbolTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "// Symbol table")?;
+        writeln!(f, "// Scope level 0:")?;
+        writeln!(f, "//\tSymbol Name       Page Address")?;
+        writeln!(f, "//\t----------------  ------------")?;
+        for (label, address) in self.iter() {
+            writeln!(f, "//\t{:<16}  {:04X}", label, address)?;
+        }
+        writeln!(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_round_trips_through_the_sym_format() {
+        let mut table = SymbolTable::new();
+        table.insert("LOOP", 0x3003);
+        table.insert("DONE", 0x300A);
+        assert!(!table.insert("LOOP", 0x3004));
+        let text = table.to_string();
+        assert!(text.contains("//\tLOOP              3003\n"));
+        assert_eq!(SymbolTable::parse(&text), table);
+        assert_eq!(table.label_at(0x300A), Some("DONE"));
+    }
+}
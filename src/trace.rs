@@ -0,0 +1,487 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use crate::disassembler::disassemble;
+use crate::registers::RegisterName;
+
+const MAGIC: &[u8; 4] = b"LC3T";
+pub const VERSION: u16 = 1;
+
+/// The registers a record can report, in order; bit `n` of the binary format's
+/// register mask stands for `TRACED_REGISTERS[n]`.
+pub const TRACED_REGISTERS: [RegisterName; 9] = [
+    RegisterName::R0,
+    RegisterName::R1,
+    RegisterName::R2,
+    RegisterName::R3,
+    RegisterName::R4,
+    RegisterName::R5,
+    RegisterName::R6,
+    RegisterName::R7,
+    RegisterName::Psr,
+];
+
+/// One executed instruction and its effects.
+///
+/// As text, a record is one line of tab separated fields: the PC, the
+/// instruction word, its disassembly, the changed registers as `R1=x0006`
+/// (PSR is listed when its privilege or priority changes), the condition
+/// codes, and the memory writes as `x3005=x0006`. Empty lists and clear
+/// condition codes are written as `-`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceRecord {
+    pub pc: u16,
+    pub instruction: u16,
+    /// Changed registers and their new values, in R0-R7, PSR order.
+    pub registers: Vec<(RegisterName, u16)>,
+    /// The N, Z and P bits after the instruction.
+    pub condition: u16,
+    /// Written addresses and their final values, in order of first write.
+    pub writes: Vec<(u16, u16)>,
+}
+
+/// Receives a record for every instruction [`crate::machine::Machine::step`]
+/// executes, once attached with `Machine::set_trace`.
+pub trait TraceSink {
+    fn record(&mut self, record: &TraceRecord);
+    /// Flushes buffered output, reporting any error since the last flush.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl TraceRecord {
+    /// Parses a line in the text format; the disassembly is not checked.
+    pub fn parse(line: &str) -> Result<TraceRecord, String> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let (pc, instruction, registers, condition, writes) = match fields.as_slice() {
+            [pc, instruction, _, registers, condition, writes] => {
+                (pc, instruction, registers, condition, writes)
+            }
+            _ => return Err(format!("expected 6 fields, found {}", fields.len())),
+        };
+        let registers = list(registers)
+            .map(|(name, value)| {
+                let register = TRACED_REGISTERS
+                    .iter()
+                    .find(|&&register| register_name(register) == name)
+                    .ok_or(format!("unknown register {}", name))?;
+                Ok((*register, hex(value)?))
+            })
+            .collect::<Result<_, String>>()?;
+        let writes = list(writes)
+            .map(|(address, value)| Ok((hex(address)?, hex(value)?)))
+            .collect::<Result<_, String>>()?;
+        let mut flags = 0;
+        if *condition != "-" {
+            for flag in condition.chars() {
+                flags |= match flag {
+                    'n' => 0b100,
+                    'z' => 0b010,
+                    'p' => 0b001,
+                    _ => return Err(format!("invalid condition codes {}", condition)),
+                };
+            }
+        }
+        Ok(TraceRecord {
+            pc: hex(pc)?,
+            instruction: hex(instruction)?,
+            registers,
+            condition: flags,
+            writes,
+        })
+    }
+
+    fn to_words(&self) -> Vec<u16> {
+        let mut mask = 0;
+        let mut values = Vec::new();
+        for (bit, register) in TRACED_REGISTERS.iter().enumerate() {
+            if let Some(&(_, value)) = self.registers.iter().find(|(name, _)| name == register) {
+                mask |= 1 << bit;
+                values.push(value);
+            }
+        }
+        let mut words = vec![self.pc, self.instruction, self.condition, mask];
+        words.extend(values);
+        words.push(self.writes.len() as u16);
+        for &(address, value) in &self.writes {
+            words.push(address);
+            words.push(value);
+        }
+        words
+    }
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "x{:04X}\tx{:04X}\t{}\t{}\t{}\t{}",
+            self.pc,
+            self.instruction,
+            disassemble(self.instruction, self.pc, None),
+            format_registers(&self.registers),
+            format_condition(self.condition),
+            format_writes(&self.writes)
+        )
+    }
+}
+
+/// Registers as in the text format, e.g. `R1=x0006 PSR=x8002`, or `-`.
+pub fn format_registers(registers: &[(RegisterName, u16)]) -> String {
+    if registers.is_empty() {
+        return "-".to_string();
+    }
+    registers
+        .iter()
+        .map(|&(register, value)| format!("{}={}", register_name(register), word(value)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Condition codes as in the text format, e.g. `z`, or `-` if none are set.
+pub fn format_condition(condition: u16) -> String {
+    let flags: String = [(0b100, 'n'), (0b010, 'z'), (0b001, 'p')]
+        .iter()
+        .filter(|(bit, _)| condition & bit != 0)
+        .map(|(_, flag)| *flag)
+        .collect();
+    if flags.is_empty() {
+        "-".to_string()
+    } else {
+        flags
+    }
+}
+
+/// Memory writes as in the text format, e.g. `x3005=x0006`, or `-`.
+pub fn format_writes(writes: &[(u16, u16)]) -> String {
+    if writes.is_empty() {
+        return "-".to_string();
+    }
+    writes
+        .iter()
+        .map(|&(address, value)| format!("{}={}", word(address), word(value)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn word(value: u16) -> String {
+    format!("x{:04X}", value)
+}
+
+pub fn register_name(register: RegisterName) -> &'static str {
+    match register {
+        RegisterName::R0 => "R0",
+        RegisterName::R1 => "R1",
+        RegisterName::R2 => "R2",
+        RegisterName::R3 => "R3",
+        RegisterName::R4 => "R4",
+        RegisterName::R5 => "R5",
+        RegisterName::R6 => "R6",
+        RegisterName::R7 => "R7",
+        RegisterName::Pc => "PC",
+        RegisterName::Cond => "CC",
+        RegisterName::Psr => "PSR",
+    }
+}
+
+//the `name=value` pairs of a list field
+fn list<'a>(field: &'a str) -> impl Iterator<Item = (&'a str, &'a str)> + 'a {
+    field
+        .split(' ')
+        .filter(move |_| field != "-")
+        .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+}
+
+fn hex(text: &str) -> Result<u16, String> {
+    text.strip_prefix('x')
+        .and_then(|digits| u16::from_str_radix(digits, 16).ok())
+        .ok_or(format!("invalid hex word {}", text))
+}
+
+/// Writes records in the text format, one line each.
+pub struct TextTrace<W: Write> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> TextTrace<W> {
+    pub fn new(writer: W) -> TextTrace<W> {
+        TextTrace {
+            writer,
+            error: None,
+        }
+    }
+}
+
+impl<W: Write> TraceSink for TextTrace<W> {
+    fn record(&mut self, record: &TraceRecord) {
+        if self.error.is_none() {
+            self.error = writeln!(self.writer, "{}", record).err();
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => self.writer.flush(),
+        }
+    }
+}
+
+/// Writes records in a compact binary format for long runs: the magic bytes
+/// `LC3T` and a version word, then for each record the big-endian words PC,
+/// instruction, condition codes, a mask of changed registers followed by
+/// their values, and the number of writes followed by address, value pairs.
+pub struct BinaryTrace<W: Write> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> BinaryTrace<W> {
+    pub fn new(mut writer: W) -> BinaryTrace<W> {
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&VERSION.to_be_bytes());
+        let error = writer.write_all(&header).err();
+        BinaryTrace { writer, error }
+    }
+}
+
+impl<W: Write> TraceSink for BinaryTrace<W> {
+    fn record(&mut self, record: &TraceRecord) {
+        if self.error.is_some() {
+            return;
+        }
+        let bytes: Vec<u8> = record
+            .to_words()
+            .iter()
+            .flat_map(|word| word.to_be_bytes())
+            .collect();
+        self.error = self.writer.write_all(&bytes).err();
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => self.writer.flush(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TraceError {
+    Io(io::Error),
+    UnsupportedVersion(u16),
+    /// Record `record`, counting from 1, could not be read.
+    Malformed {
+        record: usize,
+        reason: String,
+    },
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceError::Io(error) => write!(f, "{}", error),
+            TraceError::UnsupportedVersion(version) => {
+                write!(f, "unsupported trace version {}", version)
+            }
+            TraceError::Malformed { record, reason } => {
+                write!(f, "trace record {}: {}", record, reason)
+            }
+        }
+    }
+}
+
+impl Error for TraceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TraceError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for TraceError {
+    fn from(error: io::Error) -> TraceError {
+        TraceError::Io(error)
+    }
+}
+
+/// Reads records from a trace in either format, telling them apart by the
+/// binary format's magic bytes.
+pub struct TraceReader<R: BufRead> {
+    reader: R,
+    binary: bool,
+    read: usize,
+}
+
+impl<R: BufRead> TraceReader<R> {
+    pub fn new(mut reader: R) -> Result<TraceReader<R>, TraceError> {
+        let binary = reader.fill_buf()?.starts_with(MAGIC);
+        if binary {
+            let mut header = [0; 6];
+            reader.read_exact(&mut header)?;
+            let version = u16::from_be_bytes([header[4], header[5]]);
+            if version != VERSION {
+                return Err(TraceError::UnsupportedVersion(version));
+            }
+        }
+        Ok(TraceReader {
+            reader,
+            binary,
+            read: 0,
+        })
+    }
+
+    fn malformed(&self, reason: String) -> TraceError {
+        TraceError::Malformed {
+            record: self.read,
+            reason,
+        }
+    }
+
+    fn next_text(&mut self) -> Result<Option<TraceRecord>, TraceError> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        self.read += 1;
+        let line = line.trim_end_matches(['\n', '\r']);
+        TraceRecord::parse(line)
+            .map(Some)
+            .map_err(|reason| self.malformed(reason))
+    }
+
+    fn word(&mut self) -> Result<u16, TraceError> {
+        let mut bytes = [0; 2];
+        match self.reader.read_exact(&mut bytes) {
+            Ok(()) => Ok(u16::from_be_bytes(bytes)),
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                Err(self.malformed("truncated record".to_string()))
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn next_binary(&mut self) -> Result<Option<TraceRecord>, TraceError> {
+        if self.reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        self.read += 1;
+        let pc = self.word()?;
+        let instruction = self.word()?;
+        let condition = self.word()?;
+        let mask = self.word()?;
+        let mut registers = Vec::new();
+        for (bit, &register) in TRACED_REGISTERS.iter().enumerate() {
+            if mask & (1 << bit) != 0 {
+                registers.push((register, self.word()?));
+            }
+        }
+        let mut writes = Vec::new();
+        for _ in 0..self.word()? {
+            writes.push((self.word()?, self.word()?));
+        }
+        Ok(Some(TraceRecord {
+            pc,
+            instruction,
+            registers,
+            condition,
+            writes,
+        }))
+    }
+}
+
+impl<R: BufRead> Iterator for TraceReader<R> {
+    type Item = Result<TraceRecord, TraceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = if self.binary {
+            self.next_binary()
+        } else {
+            self.next_text()
+        };
+        next.transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records() -> Vec<TraceRecord> {
+        vec![
+            TraceRecord {
+                pc: 0x3000,
+                instruction: 0x5260,
+                registers: vec![(RegisterName::R1, 0)],
+                condition: 0b010,
+                writes: vec![],
+            },
+            TraceRecord {
+                pc: 0x3003,
+                instruction: 0x3201,
+                registers: vec![],
+                condition: 0b001,
+                writes: vec![(0x3005, 6)],
+            },
+            TraceRecord {
+                pc: 0x3004,
+                instruction: 0x8000,
+                registers: vec![(RegisterName::R6, 0xFE00), (RegisterName::Psr, 0x8002)],
+                condition: 0,
+                writes: vec![(0x2FFF, 0x0002), (0x2FFE, 0x3005)],
+            },
+        ]
+    }
+
+    #[test]
+    fn it_writes_records_as_text() {
+        let mut trace = TextTrace::new(Vec::new());
+        for record in records() {
+            trace.record(&record);
+        }
+        trace.flush().unwrap();
+        assert_eq!(
+            String::from_utf8(trace.writer).unwrap(),
+            "x3000\tx5260\tAND R1, R1, #0\tR1=x0000\tz\t-\n\
+             x3003\tx3201\tST R1, x3005\t-\tp\tx3005=x0006\n\
+             x3004\tx8000\tRTI\tR6=xFE00 PSR=x8002\t-\tx2FFF=x0002 x2FFE=x3005\n"
+        );
+    }
+
+    #[test]
+    fn it_reads_back_either_format() {
+        let mut text = TextTrace::new(Vec::new());
+        let mut binary = BinaryTrace::new(Vec::new());
+        for record in records() {
+            text.record(&record);
+            binary.record(&record);
+        }
+        for bytes in [text.writer, binary.writer].iter() {
+            let read: Vec<TraceRecord> = TraceReader::new(&bytes[..])
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+            assert_eq!(read, records());
+        }
+    }
+
+    #[test]
+    fn it_reports_malformed_records() {
+        let text = b"x3000\tx5260\tAND R1, R1, #0\tR9=x0000\tz\t-\n";
+        match TraceReader::new(&text[..]).unwrap().next() {
+            Some(Err(TraceError::Malformed { record: 1, reason })) => {
+                assert_eq!(reason, "unknown register R9")
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        let binary = b"LC3T\x00\x01\x30\x00\x52";
+        assert!(matches!(
+            TraceReader::new(&binary[..]).unwrap().next(),
+            Some(Err(TraceError::Malformed { record: 1, .. }))
+        ));
+    }
+}
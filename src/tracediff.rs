@@ -0,0 +1,376 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::registers::RegisterName;
+use crate::trace::{
+    format_condition, format_registers, format_writes, register_name, TraceError, TraceRecord,
+    TRACED_REGISTERS,
+};
+
+/// How far into either trace to look for the other's first instruction, for
+/// traces that start at different points, e.g. one includes OS start up code.
+pub const ALIGNMENT_WINDOW: usize = 1024;
+//matching instructions shown before a divergence
+const CONTEXT: usize = 5;
+
+/// Registers and memory as far as a trace has revealed them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TraceState {
+    /// R0-R7 and PSR, in `TRACED_REGISTERS` order; `None` until written.
+    pub registers: [Option<u16>; 9],
+    pub condition: Option<u16>,
+    pub memory: BTreeMap<u16, u16>,
+}
+
+impl TraceState {
+    pub fn apply(&mut self, record: &TraceRecord) {
+        for &(register, value) in &record.registers {
+            if let Some(index) = TRACED_REGISTERS.iter().position(|&r| r == register) {
+                self.registers[index] = Some(value);
+            }
+        }
+        self.condition = Some(record.condition);
+        self.memory.extend(record.writes.iter().copied());
+    }
+
+    pub fn register(&self, register: RegisterName) -> Option<u16> {
+        let index = TRACED_REGISTERS.iter().position(|&r| r == register)?;
+        self.registers[index]
+    }
+}
+
+/// The first point at which two traces disagree.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence {
+    /// The position of the divergent instruction in the aligned traces,
+    /// counting from 1.
+    pub index: usize,
+    /// Leading records skipped to align each trace.
+    pub skipped: (usize, usize),
+    /// The divergent records; `None` if that trace ended first.
+    pub ours: Option<TraceRecord>,
+    pub theirs: Option<TraceRecord>,
+    /// What differs between the two records.
+    pub differences: Vec<String>,
+    /// The matching records just before the divergence, oldest first.
+    pub context: Vec<TraceRecord>,
+    /// Each trace's state before the divergent instruction.
+    pub ours_state: TraceState,
+    pub theirs_state: TraceState,
+}
+
+/// The outcome of comparing two whole traces.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Comparison {
+    /// Both traces ran the same `count` instructions after alignment.
+    Match {
+        count: usize,
+        skipped: (usize, usize),
+    },
+    Diverged(Box<Divergence>),
+}
+
+type Records<'a> = Box<dyn Iterator<Item = Result<TraceRecord, TraceError>> + 'a>;
+
+/// Aligns `ours` and `theirs` and compares them record by record. PC,
+/// instruction, changed registers, condition codes and memory writes must all
+/// agree; the disassembly is ignored.
+pub fn compare<'a, A, B>(ours: A, theirs: B) -> Result<Comparison, TraceError>
+where
+    A: Iterator<Item = Result<TraceRecord, TraceError>> + 'a,
+    B: Iterator<Item = Result<TraceRecord, TraceError>> + 'a,
+{
+    let mut ours: Records<'a> = Box::new(ours);
+    let mut theirs: Records<'a> = Box::new(theirs);
+    let ours_head = head(&mut ours)?;
+    let theirs_head = head(&mut theirs)?;
+    let skipped = alignment(&ours_head, &theirs_head);
+
+    let mut ours_state = TraceState::default();
+    let mut theirs_state = TraceState::default();
+    for record in &ours_head[..skipped.0] {
+        ours_state.apply(record);
+    }
+    for record in &theirs_head[..skipped.1] {
+        theirs_state.apply(record);
+    }
+    let mut ours = ours_head.into_iter().skip(skipped.0).map(Ok).chain(ours);
+    let mut theirs = theirs_head
+        .into_iter()
+        .skip(skipped.1)
+        .map(Ok)
+        .chain(theirs);
+
+    let mut context = Vec::new();
+    let mut count = 0;
+    loop {
+        let (ours_record, theirs_record) =
+            match (ours.next().transpose()?, theirs.next().transpose()?) {
+                (None, None) => return Ok(Comparison::Match { count, skipped }),
+                pair => pair,
+            };
+        let differences = match (&ours_record, &theirs_record) {
+            (Some(ours), Some(theirs)) => differences(ours, theirs),
+            (None, _) => vec!["our trace ends first".to_string()],
+            (_, None) => vec!["their trace ends first".to_string()],
+        };
+        count += 1;
+        if !differences.is_empty() {
+            return Ok(Comparison::Diverged(Box::new(Divergence {
+                index: count,
+                skipped,
+                ours: ours_record,
+                theirs: theirs_record,
+                differences,
+                context,
+                ours_state,
+                theirs_state,
+            })));
+        }
+        if let (Some(ours), Some(theirs)) = (ours_record, theirs_record) {
+            ours_state.apply(&ours);
+            theirs_state.apply(&theirs);
+            if context.len() == CONTEXT {
+                context.remove(0);
+            }
+            context.push(ours);
+        }
+    }
+}
+
+fn head(records: &mut Records) -> Result<Vec<TraceRecord>, TraceError> {
+    records.by_ref().take(ALIGNMENT_WINDOW).collect()
+}
+
+/// The fewest leading records to skip in one trace so that both start at the
+/// same instruction, or none if neither start appears in the other's window.
+fn alignment(ours: &[TraceRecord], theirs: &[TraceRecord]) -> (usize, usize) {
+    let position = |records: &[TraceRecord], start: Option<&TraceRecord>| {
+        let start = start?;
+        records
+            .iter()
+            .position(|record| record.pc == start.pc && record.instruction == start.instruction)
+    };
+    match (
+        position(ours, theirs.first()),
+        position(theirs, ours.first()),
+    ) {
+        (Some(skip_ours), Some(skip_theirs)) if skip_theirs < skip_ours => (0, skip_theirs),
+        (Some(skip_ours), _) => (skip_ours, 0),
+        (None, Some(skip_theirs)) => (0, skip_theirs),
+        (None, None) => (0, 0),
+    }
+}
+
+fn differences(ours: &TraceRecord, theirs: &TraceRecord) -> Vec<String> {
+    let mut differences = Vec::new();
+    let mut differ = |what: &str, ours: String, theirs: String| {
+        if ours != theirs {
+            differences.push(format!("{}: ours {}, theirs {}", what, ours, theirs));
+        }
+    };
+    differ("PC", word(ours.pc), word(theirs.pc));
+    differ(
+        "instruction",
+        word(ours.instruction),
+        word(theirs.instruction),
+    );
+    differ(
+        "registers",
+        format_registers(&sorted(&ours.registers)),
+        format_registers(&sorted(&theirs.registers)),
+    );
+    differ(
+        "condition codes",
+        format_condition(ours.condition),
+        format_condition(theirs.condition),
+    );
+    //simulators may order the writes of one instruction differently
+    let mut ours_writes = ours.writes.clone();
+    let mut theirs_writes = theirs.writes.clone();
+    ours_writes.sort_unstable();
+    theirs_writes.sort_unstable();
+    differ(
+        "memory writes",
+        format_writes(&ours_writes),
+        format_writes(&theirs_writes),
+    );
+    differences
+}
+
+fn sorted(registers: &[(RegisterName, u16)]) -> Vec<(RegisterName, u16)> {
+    let mut sorted = registers.to_vec();
+    sorted.sort_by_key(|&(register, _)| TRACED_REGISTERS.iter().position(|&r| r == register));
+    sorted
+}
+
+fn word(value: u16) -> String {
+    format!("x{:04X}", value)
+}
+
+fn known(value: Option<u16>) -> String {
+    value.map_or("?".to_string(), word)
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "traces diverge at instruction {}", self.index)?;
+        if self.skipped != (0, 0) {
+            writeln!(
+                f,
+                "(after skipping {} of our records and {} of theirs to align them)",
+                self.skipped.0, self.skipped.1
+            )?;
+        }
+        for difference in &self.differences {
+            writeln!(f, "  {}", difference)?;
+        }
+        writeln!(f, "\npreceding instructions:")?;
+        for record in &self.context {
+            writeln!(f, "         {}", record)?;
+        }
+        let record = |record: &Option<TraceRecord>| match record {
+            Some(record) => record.to_string(),
+            None => "(end of trace)".to_string(),
+        };
+        writeln!(f, "ours:    {}", record(&self.ours))?;
+        writeln!(f, "theirs:  {}", record(&self.theirs))?;
+
+        writeln!(f, "\nstate before the divergent instruction:")?;
+        writeln!(f, "         ours   theirs")?;
+        for &register in TRACED_REGISTERS.iter() {
+            let ours = self.ours_state.register(register);
+            let theirs = self.theirs_state.register(register);
+            let marker = if ours != theirs { "  *" } else { "" };
+            writeln!(
+                f,
+                "  {:<4}  {:>5}    {:>5}{}",
+                register_name(register),
+                known(ours),
+                known(theirs),
+                marker
+            )?;
+        }
+        let condition =
+            |state: &TraceState| state.condition.map_or("?".to_string(), format_condition);
+        writeln!(
+            f,
+            "  CC    {:>5}    {:>5}",
+            condition(&self.ours_state),
+            condition(&self.theirs_state)
+        )?;
+
+        let addresses: BTreeSet<u16> = self
+            .ours_state
+            .memory
+            .keys()
+            .chain(self.theirs_state.memory.keys())
+            .copied()
+            .filter(|address| {
+                self.ours_state.memory.get(address) != self.theirs_state.memory.get(address)
+            })
+            .collect();
+        if addresses.is_empty() {
+            writeln!(f, "\nmemory written so far agrees")?;
+        } else {
+            writeln!(f, "\nmemory written so far that differs:")?;
+            for address in addresses {
+                writeln!(
+                    f,
+                    "  {}  {:>5}    {:>5}",
+                    word(address),
+                    known(self.ours_state.memory.get(&address).copied()),
+                    known(self.theirs_state.memory.get(&address).copied())
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(pc: u16, instruction: u16, r1: u16, writes: Vec<(u16, u16)>) -> TraceRecord {
+        TraceRecord {
+            pc,
+            instruction,
+            registers: vec![(RegisterName::R1, r1)],
+            condition: 0b001,
+            writes,
+        }
+    }
+
+    fn trace(records: Vec<TraceRecord>) -> impl Iterator<Item = Result<TraceRecord, TraceError>> {
+        records.into_iter().map(Ok)
+    }
+
+    fn program() -> Vec<TraceRecord> {
+        vec![
+            record(0x3000, 0x1261, 1, vec![]),
+            record(0x3001, 0x1261, 2, vec![]),
+            record(0x3002, 0x3201, 2, vec![(0x3004, 2)]),
+            record(0x3003, 0x1261, 3, vec![]),
+        ]
+    }
+
+    #[test]
+    fn it_matches_identical_traces() {
+        assert_eq!(
+            compare(trace(program()), trace(program())).unwrap(),
+            Comparison::Match {
+                count: 4,
+                skipped: (0, 0)
+            }
+        );
+    }
+
+    #[test]
+    fn it_aligns_traces_that_start_later() {
+        let mut longer = vec![record(0x0200, 0x2C01, 0, vec![])];
+        longer.extend(program());
+        assert_eq!(
+            compare(trace(program()), trace(longer)).unwrap(),
+            Comparison::Match {
+                count: 4,
+                skipped: (0, 1)
+            }
+        );
+    }
+
+    #[test]
+    fn it_reports_the_first_divergence_with_context() {
+        let mut theirs = program();
+        theirs[2].writes = vec![(0x3004, 7)];
+        theirs[3].registers = vec![(RegisterName::R1, 9)];
+        let divergence = match compare(trace(program()), trace(theirs)).unwrap() {
+            Comparison::Diverged(divergence) => divergence,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(divergence.index, 3);
+        assert_eq!(
+            divergence.differences,
+            vec!["memory writes: ours x3004=x0002, theirs x3004=x0007"]
+        );
+        assert_eq!(divergence.context.len(), 2);
+        assert_eq!(divergence.ours_state.register(RegisterName::R1), Some(2));
+        let report = divergence.to_string();
+        assert!(report.starts_with("traces diverge at instruction 3\n"));
+        assert!(report.contains("  R1    x0002    x0002\n"));
+        assert!(report.contains("  R2        ?        ?\n"));
+    }
+
+    #[test]
+    fn it_reports_a_trace_ending_early() {
+        let shorter = program()[..3].to_vec();
+        match compare(trace(program()), trace(shorter)).unwrap() {
+            Comparison::Diverged(divergence) => {
+                assert_eq!(divergence.index, 4);
+                assert_eq!(divergence.theirs, None);
+                assert!(divergence.to_string().contains("theirs:  (end of trace)"));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
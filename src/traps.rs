@@ -0,0 +1,9 @@
+#[derive(FromPrimitive)]
+pub enum Traps {
+    Getc = 0x20,  //read a character without echo
+    Out = 0x21,   //write the character in R0
+    Puts = 0x22,  //write the string at R0, one character per word
+    In = 0x23,    //prompt for and echo a character
+    Putsp = 0x24, //write the string at R0, two characters per word
+    Halt = 0x25,  //stop the machine
+}
@@ -7,6 +7,24 @@ pub fn sign_extend(x: u16, bit_count: usize) -> u16 {
     }
 }
 
+/// Parses a word written as `x3000`, `0x3000`, `#-5` or `12288`. Negative
+/// decimals are taken as two's complement.
+pub fn parse_word(text: &str) -> Option<u16> {
+    let hex = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix('x'))
+        .or_else(|| text.strip_prefix('X'));
+    if let Some(digits) = hex {
+        return u16::from_str_radix(digits, 16).ok();
+    }
+    let decimal: i32 = text.strip_prefix('#').unwrap_or(text).parse().ok()?;
+    if (-0x8000..=0xFFFF).contains(&decimal) {
+        Some(decimal as u16)
+    } else {
+        None
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -20,4 +38,15 @@ mod test {
         assert_eq!(sign_extend(1, 8) as i16, 1);
         assert_eq!(sign_extend(1, 9) as i16, 1);
     }
+
+    #[test]
+    fn it_parses_words() {
+        assert_eq!(parse_word("x3000"), Some(0x3000));
+        assert_eq!(parse_word("0xFE00"), Some(0xFE00));
+        assert_eq!(parse_word("512"), Some(512));
+        assert_eq!(parse_word("#-1"), Some(0xFFFF));
+        assert_eq!(parse_word("x10000"), None);
+        assert_eq!(parse_word("65536"), None);
+        assert_eq!(parse_word("LOOP"), None);
+    }
 }
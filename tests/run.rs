@@ -0,0 +1,34 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+use lc3::assembler::assemble;
+
+/// Assembles `source` into a scratch `.obj` file and runs it with `lc3 run --os`.
+fn run_with_os(name: &str, source: &str) -> Output {
+    let path: PathBuf = env::temp_dir().join(format!("lc3-{}-{}.obj", name, std::process::id()));
+    let program = assemble(source).unwrap();
+    fs::write(&path, program.object.to_bytes()).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_lc3"))
+        .arg("run")
+        .arg("--os")
+        .arg(&path)
+        .output()
+        .unwrap();
+    fs::remove_file(&path).ok();
+    output
+}
+
+#[test]
+fn it_exits_successfully_after_halt() {
+    let output = run_with_os("halt", ".ORIG x3000\nHALT\n.END");
+    assert!(output.status.success());
+}
+
+#[test]
+fn it_exits_with_an_error_when_the_os_handles_a_fault() {
+    let output = run_with_os("fault", ".ORIG x3000\n.FILL xD000\n.END");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("illegal opcode"));
+}